@@ -0,0 +1,142 @@
+use config::{Config, ConfigError, File as ConfFile};
+use log::{error, info};
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::twitch::chat_bot::{command_set::CommandSet, ChatSettings};
+
+/// Extensions the config crate can read, in the order it looks for them
+const CONFIG_EXTENSIONS: [&str; 6] = ["toml", "json", "yaml", "yml", "hjson", "ini"];
+
+/// Finds the file `config::File::with_name` would read for a name without extension
+#[must_use]
+pub fn find_config_file(name: &str) -> PathBuf {
+    let exact = PathBuf::from(name);
+    if exact.is_file() {
+        return exact;
+    }
+
+    CONFIG_EXTENSIONS
+        .iter()
+        .map(|ext| exact.with_extension(ext))
+        .find(|path| path.is_file())
+        .unwrap_or(exact)
+}
+
+/// A file that's polled for changes from the main loop
+pub struct WatchedFile {
+    pub path: PathBuf,
+    modified: Option<SystemTime>,
+    last_check: Instant,
+    check_interval: Duration,
+}
+
+impl WatchedFile {
+    #[must_use]
+    pub fn new(path: PathBuf) -> Self {
+        let modified = modified_time(&path);
+
+        Self {
+            path,
+            modified,
+            last_check: Instant::now(),
+            check_interval: Duration::from_secs(1),
+        }
+    }
+
+    /// True once for every change to the file, checks at most once per second
+    pub fn changed(&mut self) -> bool {
+        if self.last_check.elapsed() < self.check_interval {
+            return false;
+        }
+        self.last_check = Instant::now();
+
+        let modified = modified_time(&self.path);
+        if modified.is_some() && modified != self.modified {
+            self.modified = modified;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Loads the file again if it changed, an invalid file is logged and the old version stays in use
+    pub fn reload<T, E: Display>(&mut self, load: impl FnOnce(&Path) -> Result<T, E>) -> Option<T> {
+        if !self.changed() {
+            return None;
+        }
+
+        match load(&self.path) {
+            Ok(value) => {
+                info!("Reloaded {}", self.path.display());
+                Some(value)
+            }
+            Err(err) => {
+                error!("Invalid {}, keeping the old version: {}", self.path.display(), err);
+                None
+            }
+        }
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Reads and validates the settings file
+/// # Errors
+/// Returns an error if the file can't be parsed or is missing required chat settings
+pub fn load_settings(path: &Path) -> Result<Config, ConfigError> {
+    let mut settings = Config::default();
+    settings.merge(ConfFile::from(path))?;
+    ChatSettings::from_config(&settings)?;
+
+    Ok(settings)
+}
+
+/// Reads and validates the commands file
+/// # Errors
+/// Returns an error if the file can't be parsed into commands
+pub fn load_commands(path: &Path) -> Result<CommandSet, ConfigError> {
+    let mut commands = Config::default();
+    commands.merge(ConfFile::from(path))?;
+
+    CommandSet::from_config(&commands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::twitch::chat_bot::json_file::TempPath;
+
+    /// Writes the file with a later modified time, so the change is seen right away
+    fn write(path: &Path, contents: &str, secs: u64) {
+        fs::write(path, contents).unwrap();
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(secs)).unwrap();
+    }
+
+    #[test]
+    fn changes_are_reloaded_and_invalid_files_are_skipped() {
+        let path = TempPath::new("reload-commands.json");
+        write(&path, r#"{ "!hi": "Hello" }"#, 0);
+        let mut file = WatchedFile::new(path.to_path_buf());
+        file.check_interval = Duration::ZERO;
+        assert!(!file.changed());
+        assert!(file.reload(load_commands).is_none());
+
+        write(&path, r#"{ "!hi": "Hey" }"#, 10);
+        assert!(file.changed());
+        assert!(!file.changed());
+
+        write(&path, r#"{ "!hi": "Hi there" }"#, 20);
+        let commands = file.reload(load_commands).unwrap();
+        assert_eq!(commands.global["!hi"].response, "Hi there");
+
+        write(&path, r#"{ "!hi": { "response": "Hi", "match": "regex", "pattern": "(" } }"#, 30);
+        assert!(file.reload(load_commands).is_none());
+        assert!(!file.changed());
+    }
+}
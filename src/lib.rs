@@ -1,6 +1,7 @@
 #![deny(rust_2018_idioms, clippy::all, clippy::pedantic)]
 #![warn(clippy::nursery)]
 pub mod discord;
+pub mod hot_reload;
 pub mod common_structs;
 pub use common_structs::*;
 pub mod twitch;
@@ -1,7 +1,5 @@
 #![deny(rust_2018_idioms, clippy::all, clippy::pedantic)]
 #![warn(clippy::nursery)]
-use config::{Config as Conf, File as ConfFile};
-
 use log::*;
 use serde_json::Result;
 use simplelog::*;
@@ -12,10 +10,22 @@ use std::fs::File;
 // Crate files
 use twitch_discord_bot::{
     discord::create_discord_bot,
-    twitch::{create_twitch_pubsub_ws, setup_twitch_chat_ws},
+    hot_reload::{find_config_file, load_commands, load_settings, WatchedFile},
+    twitch::{
+        chat_bot::{chat_log::ChatLog, ChatError, ChatSettings},
+        create_twitch_pubsub_ws, setup_twitch_chat_ws,
+        stream_status::SharedStreamStatus,
+    },
 };
 
 fn main() -> Result<()> {
+    // `search <channel> <user> [count]` prints a chatter's latest logged messages instead of running the bot
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("search") {
+        search_chat_log(&args[1..]);
+        return Ok(());
+    }
+
     // Initialize logger here
     CombinedLogger::init(
         vec![
@@ -24,23 +34,27 @@ fn main() -> Result<()> {
         ]
     ).unwrap();
 
-    let mut settings = Conf::default();
-    let mut commands = Conf::default();
-
-    settings
-        .merge(ConfFile::with_name("config"))
+    // Both files are watched and reloaded from the main loop
+    let mut settings_file = WatchedFile::new(find_config_file("config"));
+    let settings = load_settings(&settings_file.path)
         .expect("Couldn't read or find configuration file");
+    let chat_settings =
+        ChatSettings::from_config(&settings).expect("Couldn't read chat settings");
     // recreating immutable Rc for settings
     let settings = Rc::new(settings);
 
-    commands
-        .merge(ConfFile::with_name("commands"))
-        .expect("Couldn't read or find commands file");
+    // Commands can be changed from chat, so they're read from (and saved to) an exact file
+    let mut commands_file = WatchedFile::new(chat_settings.commands_file);
+    let mut commands = load_commands(&commands_file.path)
+        .expect("Couldn't read or parse commands file");
+
+    // PubSub knows when channels go live, chat needs to know it too
+    let stream_status = SharedStreamStatus::default();
 
     // Twitch chat bot creates a connection initially
-    let mut twitch_chat_bot = setup_twitch_chat_ws();
+    let mut twitch_chat_bot = setup_twitch_chat_ws(&settings, stream_status.clone());
     // Twitch pubsub & Discord bot needs to call setup()
-    let mut twitch_pubsub_bot = create_twitch_pubsub_ws(settings);
+    let mut twitch_pubsub_bot = create_twitch_pubsub_ws(settings, stream_status);
     let mut discord_bot = create_discord_bot();
 
 
@@ -49,12 +63,33 @@ fn main() -> Result<()> {
     discord_bot.setup();
 
     info!("Starting bot ...");
+    let mut chat_running = true;
     std::thread::sleep(Duration::from_millis(200));
     loop {
+        if let Some(new_settings) = settings_file.reload(load_settings) {
+            if let Ok(chat_settings) = ChatSettings::from_config(&new_settings) {
+                twitch_chat_bot.apply_settings(&chat_settings);
+            }
+            twitch_pubsub_bot.apply_settings(new_settings);
+        }
+
+        if let Some(new_commands) = commands_file.reload(load_commands) {
+            commands = new_commands;
+        }
 
         discord_bot.main();
 
-        twitch_chat_bot.main(&commands);
+        // A refused token won't work on reconnect either, Discord and PubSub keep running
+        if chat_running {
+            if let Err(ChatError::LoginFailed) = twitch_chat_bot.main(&mut commands) {
+                error!("Twitch chat is stopped until the bot is restarted with a valid T_OAUTH_TOKEN");
+                chat_running = false;
+            }
+            twitch_chat_bot.run_timers();
+            twitch_chat_bot.pay_points();
+            twitch_chat_bot.check_raffles();
+            twitch_chat_bot.check_polls();
+        }
 
         twitch_pubsub_bot.main();
         // MAKE SURE THIS IS IN THE MAIN LOOP
@@ -63,3 +98,30 @@ fn main() -> Result<()> {
         // 👇 main loop ends
     }
 }
+
+fn search_chat_log(args: &[String]) {
+    fn usage<T>() -> T {
+        eprintln!("Usage: twitch-discord-bot search <channel> <user> [count]");
+        std::process::exit(2);
+    }
+    let (channel, user) = match args {
+        [channel, user] | [channel, user, _] => (channel.trim_start_matches('#').to_lowercase(), user),
+        _ => usage(),
+    };
+    let count = args.get(2).map_or(Some(20), |count| count.parse().ok()).unwrap_or_else(usage);
+
+    let settings = load_settings(&find_config_file("config"))
+        .expect("Couldn't read or find configuration file");
+    let chat_log = ChatLog::from_config(&settings).expect("Couldn't read the chat_log setting");
+    if chat_log.dir.is_none() {
+        eprintln!("Chat isn't logged, set chat_log.dir in the settings");
+        std::process::exit(1);
+    }
+
+    for entry in chat_log
+        .search(&channel, user.trim_start_matches('@'), count)
+        .expect("Couldn't read the chat logs")
+    {
+        println!("{entry}");
+    }
+}
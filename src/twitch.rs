@@ -1,21 +1,59 @@
-use std::{rc::Rc, env::var};
+use std::{collections::HashMap, rc::Rc, env::var};
 use std::time::{Duration, Instant};
 
 pub mod chat_bot;
+pub mod stream_status;
 pub mod topics_bot;
-use chat_bot::TwidshTshadBott;
+use chat_bot::{
+    cooldowns::Cooldowns, counters::Counters, moderation::Permits, points::Points, polls::Polls,
+    punishments::Offences, quotes::Quotes, raffle::Raffles, send_msg::ChatSender, timers::Timers,
+    ChatSettings, TwidshTshadBott,
+};
+use stream_status::SharedStreamStatus;
 use topics_bot::TwidshPubSubBott;
 
 use crate::common_structs::socket::setup_socket;
 
 use log::*;
 
-pub fn setup_twitch_chat_ws() -> TwidshTshadBott {
+pub fn setup_twitch_chat_ws(
+    settings: &config::Config,
+    stream_status: SharedStreamStatus,
+) -> TwidshTshadBott {
     let url = "wss://irc-ws.chat.twitch.tv:443";
     let oauth_token = var("T_OAUTH_TOKEN").expect("Twitch chat token not found");
+    let ChatSettings {
+        nick,
+        channels,
+        commands_file,
+        command_style,
+        timers,
+        counters_file,
+        counter_permission,
+        quotes_file,
+        moderation,
+        events,
+        cheers,
+        chat_log,
+        points_file,
+        points: points_settings,
+        raffle: raffle_settings,
+        poll: poll_settings,
+    } = ChatSettings::from_config(settings).expect("Chat settings (bot_nick, channels) not found");
 
     let back_off_timer = Duration::from_secs(2);
     let last_back_off = None;
+    let mut chat_timers = Timers::default();
+    chat_timers.set(timers);
+    let counters = Counters::load(counters_file).expect("Couldn't read counters file");
+    let quotes = Quotes::load(quotes_file).expect("Couldn't read quotes file");
+    let points = Points::load(points_file).expect("Couldn't read points file");
+    let history_file = moderation
+        .ladder()
+        .and_then(|ladder| ladder.history_file.clone());
+    let offences = Offences::load(history_file).expect("Couldn't read moderation offences file");
+    let mut sender = ChatSender::default();
+    sender.set_log(chat_log.clone(), &nick);
 
     info!("Setting up Twitch Chat WS");
     let socket = setup_socket(url.to_string());
@@ -24,12 +62,39 @@ pub fn setup_twitch_chat_ws() -> TwidshTshadBott {
         socket,
         socket_url: url.to_string(),
         oauth_token,
+        nick,
+        channels,
+        commands_file,
+        command_style,
+        cooldowns: Cooldowns::default(),
+        sender,
+        command_uses: HashMap::new(),
+        timers: chat_timers,
+        counters,
+        counter_permission,
+        quotes,
+        moderation,
+        permits: Permits::default(),
+        offences,
+        events,
+        cheers,
+        chat_log,
+        points,
+        points_settings,
+        raffles: Raffles::default(),
+        raffle_settings,
+        polls: Polls::default(),
+        poll_settings,
+        stream_status,
         back_off_timer,
         last_back_off,
     }
 }
 
-pub fn create_twitch_pubsub_ws(settings: Rc<config::Config>) -> TwidshPubSubBott {
+pub fn create_twitch_pubsub_ws(
+    settings: Rc<config::Config>,
+    stream_status: SharedStreamStatus,
+) -> TwidshPubSubBott {
     let channel_id = var("T_CHANNEL_ID").expect("Twitch channel id not found");
     let socket_url = "wss://pubsub-edge.twitch.tv";
 
@@ -51,6 +116,7 @@ pub fn create_twitch_pubsub_ws(settings: Rc<config::Config>) -> TwidshPubSubBott
         expected_pong,
         last_ping: Instant::now(),
         pong_timeout,
-        settings: settings.clone()
+        settings: settings.clone(),
+        stream_status,
     }
 }
@@ -1,47 +1,399 @@
-use config::Config;
 use native_tls::TlsStream;
+use std::collections::HashMap;
 use std::net::TcpStream;
+use std::path::PathBuf;
 use std::time::{Duration, Instant};
 use tungstenite::stream::Stream;
 use tungstenite::{Message, WebSocket};
 use log::*;
 
+pub mod chat_log;
 pub mod command_parser;
+pub mod command_set;
+pub mod cooldowns;
+pub mod counters;
+pub mod events;
+pub mod json_file;
+pub mod manage_commands;
+pub mod moderation;
 pub mod msg_parser;
+pub mod permissions;
+pub mod points;
+pub mod polls;
+pub mod punishments;
+pub mod quotes;
+pub mod raffle;
 pub mod send_msg;
+pub mod template;
+pub mod timers;
 
-use msg_parser::parse_twitch_msg;
-use command_parser::chat_commands;
+use msg_parser::{parse_twitch_msg, ChatNotice, TwitchChatMsg, TwitchMessage};
+use command_parser::{chat_commands, CommandStyle};
+use command_set::CommandSet;
+use cooldowns::Cooldowns;
+use counters::Counters;
+use chat_log::{moderation_event, ChatLog, LogKind};
+use events::{cheer_action, CheerAction, ChatEvent, EventAction, CHEER_VARS, EVENT_KINDS, EVENT_VARS};
+use moderation::{ModerationFilter, ModerationSettings, Permits};
+use permissions::Permission;
+use punishments::Offences;
+use points::{Points, PointsSettings};
+use polls::{PollSettings, Polls};
+use quotes::Quotes;
+use raffle::{RaffleSettings, Raffles};
+use send_msg::ChatSender;
+use template::{render, TemplateVars};
+use timers::{Timer, Timers};
 
 use crate::common_structs::socket::{Disconnected, Result, setup_socket};
+use crate::notif;
+use crate::twitch::stream_status::SharedStreamStatus;
+
+/// Why reading chat failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatError {
+    /// The connection dropped, reconnecting can fix it
+    Disconnected,
+    /// Twitch refused the token, reconnecting with it would fail again
+    LoginFailed,
+}
+
+impl From<Disconnected> for ChatError {
+    fn from(_: Disconnected) -> Self {
+        Self::Disconnected
+    }
+}
+
+/// Chat settings read from the settings file
+pub struct ChatSettings {
+    pub nick: String,
+    pub channels: Vec<String>,
+    pub commands_file: PathBuf,
+    pub command_style: CommandStyle,
+    pub timers: Vec<Timer>,
+    pub counters_file: PathBuf,
+    /// Who can change counters from chat
+    pub counter_permission: Permission,
+    pub quotes_file: PathBuf,
+    pub moderation: ModerationFilter,
+    /// What to do on subs, raids and other events, by event name
+    pub events: HashMap<String, EventAction>,
+    /// What to do on cheers, by the least bits needed
+    pub cheers: Vec<CheerAction>,
+    /// Where chat, moderation and the bot's replies are logged
+    pub chat_log: ChatLog,
+    /// Where loyalty points are saved
+    pub points_file: PathBuf,
+    /// How viewers earn loyalty points
+    pub points: PointsSettings,
+    /// Defaults for raffles and where their results go
+    pub raffle: RaffleSettings,
+    /// How long polls run unless a duration is given
+    pub poll: PollSettings,
+}
+
+/// Timers can't post more often than this
+const MIN_TIMER_INTERVAL: u64 = 60;
+
+impl ChatSettings {
+    /// # Errors
+    /// Returns an error if `bot_nick` or `channels` is missing or has the wrong type,
+    /// or a timer is invalid
+    pub fn from_config(settings: &config::Config) -> std::result::Result<Self, config::ConfigError> {
+        let nick = settings.get_str("bot_nick")?;
+        let channels = settings
+            .get::<Vec<String>>("channels")?
+            .iter()
+            .map(|channel| channel.trim_start_matches('#').to_lowercase())
+            .collect();
+        let commands_file = settings
+            .get_str("commands_file")
+            .unwrap_or_else(|_| "commands.json".to_string())
+            .into();
+        let defaults = CommandStyle::default();
+        let command_style = CommandStyle {
+            prefix: settings.get_str("command_prefix").unwrap_or(defaults.prefix),
+            case_sensitive: settings
+                .get_bool("case_sensitive_commands")
+                .unwrap_or(defaults.case_sensitive),
+        };
+
+        let counters_file = settings
+            .get_str("counters_file")
+            .unwrap_or_else(|_| "counters.json".to_string())
+            .into();
+        let counter_permission = match settings.get::<Permission>("counter_permission") {
+            Err(config::ConfigError::NotFound(_)) => Permission::Moderator,
+            permission => permission?,
+        };
+
+        let quotes_file = settings
+            .get_str("quotes_file")
+            .unwrap_or_else(|_| "quotes.json".to_string())
+            .into();
+
+        let moderation = match settings.get::<ModerationSettings>("moderation") {
+            Err(config::ConfigError::NotFound(_)) => ModerationSettings::default(),
+            moderation => moderation?,
+        };
+        let moderation = ModerationFilter::new(moderation)?;
+
+        let events = match settings.get::<HashMap<String, EventAction>>("events") {
+            Err(config::ConfigError::NotFound(_)) => HashMap::new(),
+            events => events?,
+        };
+        for (kind, action) in &events {
+            if !EVENT_KINDS.contains(&kind.as_str()) {
+                return Err(config::ConfigError::Message(format!(
+                    "unknown event {kind}, it should be one of {}",
+                    EVENT_KINDS.join(", ")
+                )));
+            }
+            action.validate(&EVENT_VARS).map_err(|err| {
+                config::ConfigError::Message(format!("event {kind}: {err}"))
+            })?;
+        }
+
+        let cheers = match settings.get::<Vec<CheerAction>>("cheers") {
+            Err(config::ConfigError::NotFound(_)) => Vec::new(),
+            cheers => cheers?,
+        };
+        for cheer in &cheers {
+            cheer.action.validate(&CHEER_VARS).map_err(|err| {
+                config::ConfigError::Message(format!("cheer of {} bits: {err}", cheer.min_bits))
+            })?;
+        }
+
+        let chat_log = ChatLog::from_config(settings)?;
+
+        let points_file = settings
+            .get_str("points_file")
+            .unwrap_or_else(|_| "points.json".to_string())
+            .into();
+        let points = PointsSettings::from_config(settings)?;
+        let raffle = RaffleSettings::from_config(settings)?;
+        let poll = PollSettings::from_config(settings)?;
+
+        let timers = match settings.get::<Vec<Timer>>("timers") {
+            Err(config::ConfigError::NotFound(_)) => Vec::new(),
+            timers => timers?,
+        };
+        for timer in &timers {
+            if timer.interval < MIN_TIMER_INTERVAL {
+                return Err(config::ConfigError::Message(format!(
+                    "timer {}: interval must be at least {MIN_TIMER_INTERVAL} seconds",
+                    timer.name
+                )));
+            }
+            template::validate(&timer.message).map_err(|err| {
+                config::ConfigError::Message(format!("timer {}: {err}", timer.name))
+            })?;
+        }
+
+        Ok(Self {
+            nick,
+            channels,
+            commands_file,
+            command_style,
+            timers,
+            counters_file,
+            counter_permission,
+            quotes_file,
+            moderation,
+            events,
+            cheers,
+            chat_log,
+            points_file,
+            points,
+            raffle,
+            poll,
+        })
+    }
+}
 
 pub struct TwidshTshadBott {
    pub socket: WebSocket<Stream<TcpStream, TlsStream<TcpStream>>>,
    pub oauth_token: String,
+   pub nick: String,
+   pub channels: Vec<String>,
+   pub commands_file: PathBuf,
+   pub command_style: CommandStyle,
+   pub cooldowns: Cooldowns,
+   pub sender: ChatSender,
+   /// Times each command has been used, per channel and trigger
+   pub command_uses: HashMap<(String, String), u64>,
+   pub timers: Timers,
+   pub counters: Counters,
+   pub counter_permission: Permission,
+   pub quotes: Quotes,
+   pub moderation: ModerationFilter,
+   pub permits: Permits,
+   pub offences: Offences,
+   pub events: HashMap<String, EventAction>,
+   pub cheers: Vec<CheerAction>,
+   pub chat_log: ChatLog,
+   pub points: Points,
+   pub points_settings: PointsSettings,
+   pub raffles: Raffles,
+   pub raffle_settings: RaffleSettings,
+   pub polls: Polls,
+   pub poll_settings: PollSettings,
+   pub stream_status: SharedStreamStatus,
    pub socket_url: String,
    pub last_back_off: Option<Instant>,
    pub back_off_timer: Duration,
 }
 
 impl TwidshTshadBott {
-    pub fn main(&mut self, commands: &Config) {
-        if let Err(Disconnected) = self.read_message(commands) {
-            self.back_off()
-        } else {
-            // println!("Chat read msg successful");
+    /// Reads chat and sends queued messages, reconnecting if the connection dropped
+    /// # Errors
+    /// Returns `LoginFailed` if Twitch refused the token, the caller decides what to do then
+    pub fn main(&mut self, commands: &mut CommandSet) -> std::result::Result<(), ChatError> {
+        match self.read_message(commands) {
+            Err(ChatError::LoginFailed) => return Err(ChatError::LoginFailed),
+            Err(ChatError::Disconnected) => self.back_off(),
+            Ok(()) => {
+                if self.sender.flush(&mut self.socket).is_err() {
+                    warn!("Could not send queued chat messages, backing off");
+                    self.back_off();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Queues the timers that are due, they're sent with the next flush
+    pub fn run_timers(&mut self) {
+        let due = self
+            .timers
+            .due(&self.channels, &self.stream_status.borrow());
+
+        for timer in due {
+            let vars = TemplateVars {
+                user: &self.nick,
+                channel: &timer.channel,
+                args: &[],
+                count: timer.count,
+                uptime: self.stream_status.borrow().uptime(&timer.channel),
+                counters: self.counters.channel(&timer.channel),
+                extra: &[],
+            };
+            self.sender.queue(&timer.channel, &render(&timer.message, &vars));
+        }
+    }
+
+    /// Pays loyalty points to viewers of live channels when they're due
+    pub fn pay_points(&mut self) {
+        let stream_status = self.stream_status.borrow();
+        if let Err(err) = self.points.pay(&stream_status, &self.points_settings, &self.nick) {
+            error!("Could not save points to {}: {}", self.points.path().display(), err);
+        }
+    }
+
+    /// Tells chat about raffle winners who didn't claim their prize in time
+    pub fn check_raffles(&mut self) {
+        for (channel, message) in self.raffles.expired(&self.raffle_settings, &self.command_style) {
+            self.sender.queue(&channel, &message);
+        }
+    }
+
+    /// Announces the results of polls whose time is up
+    pub fn check_polls(&mut self) {
+        for (channel, result) in self.polls.finished() {
+            self.sender.queue(&channel, &result);
+        }
+    }
+
+    /// # Errors
+    /// Returns `LoginFailed` if the login failed, reconnecting with the same token would fail again
+    fn handle_notice(&mut self, notice: &ChatNotice) -> std::result::Result<(), ChatError> {
+        match notice {
+            ChatNotice::LoginFailed(_) => {
+                error!("{}, check T_OAUTH_TOKEN", notice);
+                return Err(ChatError::LoginFailed);
+            }
+            ChatNotice::RateLimited { .. } => {
+                warn!("{}, pausing chat messages", notice);
+                self.sender.rate_limited();
+            }
+            ChatNotice::Banned { .. } => error!("{}", notice),
+            ChatNotice::Other { .. } => info!("{}", notice),
+        }
+
+        Ok(())
+    }
+
+    /// Thanks subs, welcomes raids and so on, as set up in the `events` setting
+    fn handle_event(&mut self, channel: &str, event: &ChatEvent) {
+        info!("#{} {} from {}", channel, event.kind(), event.user());
+        if let Some(action) = self.events.get(event.kind()).cloned() {
+            let title = format!("{} in #{channel}", event.kind());
+            self.run_action(channel, &title, event.user(), &action, &event.vars());
+        }
+    }
+
+    /// Adds a cheer to the stream's total and thanks the cheerer, as set up in the `cheers` setting
+    fn handle_cheer(&mut self, msg: &TwitchChatMsg) {
+        let total = self
+            .stream_status
+            .borrow_mut()
+            .add_bits(&msg.channel_name, msg.bits);
+        info!(
+            "<{}> cheered {} bits in #{}, {} this stream",
+            msg.display_name, msg.bits, msg.channel_name, total
+        );
+
+        if let Some(action) = cheer_action(&self.cheers, msg.bits).cloned() {
+            let extra = [
+                ("bits", msg.bits.to_string()),
+                ("total_bits", total.to_string()),
+                ("message", msg.message.clone()),
+            ];
+            let title = format!("{} bits in #{}", msg.bits, msg.channel_name);
+            self.run_action(&msg.channel_name, &title, &msg.display_name, &action, &extra);
         }
     }
 
-    pub fn send_ping(&mut self) -> Result<()> {
-        // Send PONG if Twitch is going PING
-        // println!("Recived Twitch Chat PING! Sent PONG!");
+    /// Sends the action's message, shows its notification and runs its program
+    fn run_action(
+        &mut self,
+        channel: &str,
+        title: &str,
+        user: &str,
+        action: &EventAction,
+        extra: &[(&str, String)],
+    ) {
+        let uptime = self.stream_status.borrow().uptime(channel);
+        let vars = TemplateVars {
+            user,
+            channel,
+            args: &[],
+            count: 0,
+            uptime,
+            counters: self.counters.channel(channel),
+            extra,
+        };
+
+        if let Some(message) = &action.message {
+            self.sender.queue(channel, &render(message, &vars));
+        }
+        if action.notify {
+            notif!(title, event_summary(user, extra).as_str(),);
+        }
+        action.run_program(&vars);
+    }
 
+    /// Answers a PING from the server, it disconnects us otherwise
+    /// # Errors
+    /// Returns `Disconnected` if the PONG couldn't be written
+    pub fn send_pong(&mut self, server: &str) -> Result<()> {
         let send_pong = self
             .socket
-            .write_message(Message::Text("PONG :tmi.twitch.tv".to_string()));
+            .write_message(Message::Text(format!("PONG :{server}")));
 
         match send_pong {
-            Ok(_) => {
+            Ok(()) => {
                 info!("Sent Twitch chat pong");
                 Ok(())
             }
@@ -52,14 +404,16 @@ impl TwidshTshadBott {
         }
     }
 
-    pub fn read_message(&mut self, commands: &Config) -> Result<()> {
+    /// # Errors
+    /// Returns `LoginFailed` if Twitch refused the token, `Disconnected` if the connection dropped
+    pub fn read_message(&mut self, commands: &mut CommandSet) -> std::result::Result<(), ChatError> {
         if !self.socket.can_read() {
             error!("Chat Cats can't read!!!");
-            return Err(Disconnected);
+            return Err(ChatError::Disconnected);
         }
         if !self.socket.can_write() {
             error!("Can't write!!!");
-            return Err(Disconnected);
+            return Err(ChatError::Disconnected);
         }
         // Twitch websockets
         match self.socket.read_message() {
@@ -71,29 +425,75 @@ impl TwidshTshadBott {
             }
             Err(err) => {
                 error!("TWITCH CHAT WS ERROR MSG:\n{}", err);
-                return Err(Disconnected);
+                return Err(ChatError::Disconnected);
             }
             Ok(Message::Text(res)) => {
-                if res.contains("PING") {
-                    if let Err(Disconnected) = self.send_ping() {
-                        return Err(Disconnected);
-                    }
-                }
-
                 // println!("{}", res.trim()); // For debugging
-                match parse_twitch_msg(res) {
-                    Some(msg) => {
-                        info!(
-                            "#{} <{}>: \"{}\"",
-                            msg.channel_name,
-                            msg.display_name,
-                            msg.message.trim()
-                        );
-                        // Respond to commands
-                        chat_commands::cmd_response(msg, &mut self.socket, &commands);
+                // Twitch may batch several IRC lines into one WS message
+                for line in res.lines() {
+                    match parse_twitch_msg(line) {
+                        Some(TwitchMessage::Privmsg(msg)) => {
+                            info!(
+                                "#{} <{}>: \"{}\"",
+                                msg.channel_name,
+                                msg.display_name,
+                                msg.message.trim()
+                            );
+                            self.chat_log
+                                .write(&msg.channel_name, LogKind::Message, &msg.login, &msg.message);
+                            if msg.bits > 0 {
+                                self.handle_cheer(&msg);
+                            }
+                            if let Some(violation) = self.moderation.check(&msg, &mut self.permits) {
+                                let reason = violation.reason;
+                                let commands = self.moderation.punish(violation, &msg, &mut self.offences);
+                                let text = format!("{reason}: {}", commands.join(" | "));
+                                self.chat_log
+                                    .write(&msg.channel_name, LogKind::Moderation, &msg.login, &text);
+                                for command in commands {
+                                    self.sender.queue(&msg.channel_name, &command);
+                                }
+                                continue;
+                            }
+                            self.timers.chat_line(&msg.channel_name);
+                            self.points.chatted(&msg.channel_name, &msg.login);
+                            // Respond to commands
+                            chat_commands::cmd_response(&msg, self, commands);
+                        }
+                        Some(TwitchMessage::UserNotice(notice)) => {
+                            if let (Some(channel), Some(event)) =
+                                (notice.channel(), ChatEvent::parse(&notice))
+                            {
+                                self.handle_event(channel, &event);
+                            }
+                        }
+                        Some(TwitchMessage::Ping(server)) => self.send_pong(&server)?,
+                        // Twitch is about to restart the server, it's fine to reconnect right away
+                        Some(TwitchMessage::Reconnect) => {
+                            info!("Twitch chat asked us to reconnect");
+                            self.reconnect();
+                            return Ok(());
+                        }
+                        Some(TwitchMessage::Notice(notice)) => self.handle_notice(&notice)?,
+                        // Sent when we join or chat, tells us if the bot is a mod in the channel
+                        Some(TwitchMessage::UserState(state)) => {
+                            let is_mod = state.tag("mod") == Some("1")
+                                || state.channel() == Some(self.nick.as_str());
+                            if let Some(channel) = state.channel() {
+                                self.sender.set_moderator(channel, is_mod);
+                            }
+                        }
+                        // Timeouts, bans and deleted messages, by anyone
+                        Some(TwitchMessage::ClearChat(irc) | TwitchMessage::ClearMsg(irc)) => {
+                            if let (Some(channel), Some((login, text))) = (irc.channel(), moderation_event(&irc)) {
+                                self.chat_log.write(channel, LogKind::Moderation, &login, &text);
+                            }
+                        }
+                        Some(TwitchMessage::Join { channel, login }) => self.points.join(&channel, &login),
+                        Some(TwitchMessage::Part { channel, login }) => self.points.part(&channel, &login),
+                        _ => {}
                     }
-                    None => {}
-                };
+                }
             }
             Ok(..) => {}
         }
@@ -111,16 +511,124 @@ impl TwidshTshadBott {
             .unwrap();
 
         self.socket
-            .write_message(Message::Text(format!("NICK idontmatterlol")))
+            .write_message(Message::Text(format!("NICK {}", self.nick)))
             .unwrap();
 
-        self.socket
-            .write_message(Message::Text(format!("JOIN #neonraytracer")))
-            .unwrap();
+        for channel in &self.channels {
+            self.socket
+                .write_message(Message::Text(format!("JOIN #{channel}")))
+                .unwrap();
+        }
 
         self.last_back_off = None;
     }
 
+    /// Applies reloaded settings, joining and leaving channels as needed
+    pub fn apply_settings(&mut self, settings: &ChatSettings) {
+        if settings.nick != self.nick {
+            info!("Bot nick changed to {}, it's used from the next reconnect", settings.nick);
+            self.nick.clone_from(&settings.nick);
+        }
+        self.command_style = settings.command_style.clone();
+        self.timers.set(settings.timers.clone());
+        self.moderation = settings.moderation.clone();
+        self.events.clone_from(&settings.events);
+        self.cheers.clone_from(&settings.cheers);
+        self.chat_log = settings.chat_log.clone();
+        self.sender.set_log(self.chat_log.clone(), &self.nick);
+        self.points_settings = settings.points.clone();
+        self.raffle_settings = settings.raffle.clone();
+        self.poll_settings = settings.poll.clone();
+        let history_file = self.moderation.ladder().and_then(|ladder| ladder.history_file.as_ref());
+        if history_file != self.offences.path() {
+            warn!("The offences history file can't be changed while running, restart the bot to use it");
+        }
+        if settings.commands_file != self.commands_file {
+            warn!("The commands file can't be changed while running, restart the bot to use it");
+        }
+        if settings.counters_file != self.counters.path() {
+            warn!("The counters file can't be changed while running, restart the bot to use it");
+        }
+        if settings.quotes_file != self.quotes.path() {
+            warn!("The quotes file can't be changed while running, restart the bot to use it");
+        }
+        if settings.points_file != self.points.path() {
+            warn!("The points file can't be changed while running, restart the bot to use it");
+        }
+        self.counter_permission = settings.counter_permission;
+
+        let parted: Vec<String> = self
+            .channels
+            .iter()
+            .filter(|channel| !settings.channels.contains(channel))
+            .cloned()
+            .collect();
+        for channel in parted {
+            if self.part_channel(&channel).is_err() {
+                self.back_off();
+            }
+        }
+        for channel in &settings.channels {
+            if self.join_channel(channel).is_err() {
+                self.back_off();
+            }
+        }
+    }
+
+    /// Joins a channel at runtime, it's rejoined on reconnect
+    /// # Errors
+    /// Returns `Disconnected` if the JOIN couldn't be written
+    pub fn join_channel(&mut self, channel: &str) -> Result<()> {
+        let channel = channel.trim_start_matches('#').to_lowercase();
+        if self.channels.contains(&channel) {
+            return Ok(());
+        }
+
+        if let Err(err) = self
+            .socket
+            .write_message(Message::Text(format!("JOIN #{channel}")))
+        {
+            error!("Could not join #{}:\n {}\n", channel, err);
+            return Err(Disconnected);
+        }
+
+        info!("Joined #{}", channel);
+        self.channels.push(channel);
+        Ok(())
+    }
+
+    /// Leaves a channel at runtime
+    /// # Errors
+    /// Returns `Disconnected` if the PART couldn't be written
+    pub fn part_channel(&mut self, channel: &str) -> Result<()> {
+        let channel = channel.trim_start_matches('#').to_lowercase();
+        if !self.channels.contains(&channel) {
+            return Ok(());
+        }
+
+        if let Err(err) = self
+            .socket
+            .write_message(Message::Text(format!("PART #{channel}")))
+        {
+            error!("Could not part #{}:\n {}\n", channel, err);
+            return Err(Disconnected);
+        }
+
+        info!("Left #{}", channel);
+        self.channels.retain(|joined| joined != &channel);
+        Ok(())
+    }
+
+    /// Opens a new connection and logs in again
+    fn reconnect(&mut self) {
+        self.socket = setup_socket(self.socket_url.clone());
+        self.send_listen_msg();
+        // Messages queued while disconnected go out now
+        if self.sender.flush(&mut self.socket).is_err() {
+            warn!("Could not send queued chat messages after reconnecting");
+        }
+    }
+
     fn back_off(&mut self) {
         let max_back_off: Duration = Duration::from_secs(120);
 
@@ -128,8 +636,7 @@ impl TwidshTshadBott {
             if last.elapsed() > self.back_off_timer && !(self.back_off_timer > max_back_off) {
                 info!("Backing off chat for REEEEEEEEEEEEEEEEALZ");
                 self.back_off_timer = self.back_off_timer * 2;
-                self.socket = setup_socket(self.socket_url.to_string());
-                self.send_listen_msg();
+                self.reconnect();
 
                 self.last_back_off = Some(Instant::now());
             } else {
@@ -141,3 +648,18 @@ impl TwidshTshadBott {
         }
     }
 }
+
+/// The user and the event variables that are set, like `Bob (months: 6, plan: Tier 1)`
+fn event_summary(user: &str, extra: &[(&str, String)]) -> String {
+    let details: Vec<_> = extra
+        .iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(name, value)| format!("{name}: {value}"))
+        .collect();
+
+    if details.is_empty() {
+        user.to_string()
+    } else {
+        format!("{user} ({})", details.join(", "))
+    }
+}
@@ -0,0 +1,305 @@
+use chrono::{DateTime, Local, NaiveDate};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::twitch::chat_bot::command_parser::{CommandCall, CommandStyle};
+use crate::twitch::chat_bot::msg_parser::{IrcMessage, TwitchChatMsg};
+use crate::twitch::chat_bot::permissions::Permission;
+
+/// Messages `!logs` shows unless asked for more, and the most it shows
+const DEFAULT_SEARCH_COUNT: usize = 3;
+const MAX_SEARCH_COUNT: usize = 10;
+
+/// What a log line is about
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogKind {
+    /// A chatter's message
+    Message,
+    /// A filter caught a chatter, the text says why and what was done
+    Moderation,
+    /// Something the bot sent
+    Reply,
+}
+
+/// A line of a log file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    /// RFC 3339 in local time
+    pub time: String,
+    pub channel: String,
+    pub kind: LogKind,
+    /// The chatter's login, or the bot's nick for replies
+    pub user: String,
+    pub text: String,
+}
+
+impl std::fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let time = DateTime::parse_from_rfc3339(&self.time)
+            .map_or_else(|_| self.time.clone(), |time| time.format("%Y-%m-%d %H:%M").to_string());
+        write!(f, "[{time}] <{}> {}", self.user, self.text)
+    }
+}
+
+/// Writes chat to a JSON lines file per channel and day, like `<dir>/<channel>/2020-11-05.jsonl`,
+/// the `chat_log` setting
+///
+/// Nothing is logged without a `dir`
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct ChatLog {
+    pub dir: Option<PathBuf>,
+    /// Older log files are deleted when a new day starts, they're kept forever if unset
+    pub keep_days: Option<u64>,
+}
+
+impl ChatLog {
+    /// Reads the `chat_log` setting, logging is off if it's missing
+    /// # Errors
+    /// Returns an error if the setting has the wrong type
+    pub fn from_config(settings: &config::Config) -> Result<Self, config::ConfigError> {
+        match settings.get::<Self>("chat_log") {
+            Err(config::ConfigError::NotFound(_)) => Ok(Self::default()),
+            chat_log => chat_log,
+        }
+    }
+
+    /// Appends a line to today's log file of the channel, errors are only logged
+    pub fn write(&self, channel: &str, kind: LogKind, user: &str, text: &str) {
+        let Some(dir) = &self.dir else {
+            return;
+        };
+
+        let now = Local::now();
+        let entry = LogEntry {
+            time: now.to_rfc3339(),
+            channel: channel.to_string(),
+            kind,
+            user: user.to_string(),
+            text: text.to_string(),
+        };
+        let channel_dir = dir.join(channel);
+        let path = channel_dir.join(format!("{}.jsonl", now.format("%Y-%m-%d")));
+        let new_day = !path.exists();
+
+        if let Err(err) = append(&path, &entry) {
+            error!("Could not write chat log {}: {}", path.display(), err);
+        }
+        if new_day {
+            self.remove_old(&channel_dir, now.naive_local().date());
+        }
+    }
+
+    /// The latest `count` messages of a chatter in a channel, oldest first
+    /// # Errors
+    /// Returns an error if a log file exists but can't be read
+    pub fn search(&self, channel: &str, user: &str, count: usize) -> io::Result<Vec<LogEntry>> {
+        let Some(dir) = &self.dir else {
+            return Ok(Vec::new());
+        };
+
+        let mut found = Vec::new();
+        // Newest day first, until there are enough
+        for path in log_files(&dir.join(channel))?.iter().rev() {
+            let day = fs::read_to_string(path)?;
+            let mut messages: Vec<_> = day
+                .lines()
+                .filter_map(|line| serde_json::from_str::<LogEntry>(line).ok())
+                .filter(|entry| entry.kind == LogKind::Message && entry.user.eq_ignore_ascii_case(user))
+                .collect();
+            messages.append(&mut found);
+            found = messages;
+
+            if found.len() >= count {
+                break;
+            }
+        }
+
+        let skip = found.len().saturating_sub(count);
+        Ok(found.split_off(skip))
+    }
+
+    /// Deletes log files older than `keep_days`
+    fn remove_old(&self, channel_dir: &Path, today: NaiveDate) {
+        let Some(keep_days) = self.keep_days else {
+            return;
+        };
+        for path in log_files(channel_dir).unwrap_or_default() {
+            let date = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| NaiveDate::parse_from_str(stem, "%Y-%m-%d").ok());
+            if date.is_some_and(|date| u64::try_from((today - date).num_days()).is_ok_and(|age| age > keep_days)) {
+                match fs::remove_file(&path) {
+                    Ok(()) => info!("Removed old chat log {}", path.display()),
+                    Err(err) => error!("Could not remove old chat log {}: {}", path.display(), err),
+                }
+            }
+        }
+    }
+}
+
+/// The chatter a CLEARCHAT or CLEARMSG was about and what happened to them,
+/// so timeouts, bans and deletions by moderators and `AutoMod` are logged too
+#[must_use]
+pub fn moderation_event(irc: &IrcMessage) -> Option<(String, String)> {
+    let target = irc.params.get(1);
+    match irc.command.as_str() {
+        "CLEARCHAT" => Some(match (target, irc.tag("ban-duration")) {
+            (Some(login), Some(seconds)) => (login.clone(), format!("timed out for {seconds}s")),
+            (Some(login), None) => (login.clone(), "banned".to_string()),
+            (None, _) => (String::new(), "chat cleared".to_string()),
+        }),
+        "CLEARMSG" => Some((
+            irc.tag("login")?.to_string(),
+            format!("message deleted: {}", target.map_or("", String::as_str)),
+        )),
+        _ => None,
+    }
+}
+
+fn append(path: &Path, entry: &LogEntry) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)
+}
+
+/// The `.jsonl` files of a channel, sorted by day, a missing directory has none
+fn log_files(channel_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(channel_dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "jsonl") {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+/// Handles `!logs <user> [count]`, showing a chatter's latest messages to moderators
+///
+/// Returns the reply for chat, or None if the message wasn't a logs command
+pub fn logs_command(
+    msg: &TwitchChatMsg,
+    call: &CommandCall<'_>,
+    chat_log: &ChatLog,
+    style: &CommandStyle,
+) -> Option<String> {
+    if !style.matches(&style.trigger("logs"), call.name) || Permission::of(msg) < Permission::Moderator {
+        return None;
+    }
+
+    let user = &msg.display_name;
+    let Some(login) = call.args.first().map(|login| login.trim_start_matches('@').to_lowercase()) else {
+        return Some(format!("@{user} usage: {}logs <user> [count]", style.prefix));
+    };
+    if chat_log.dir.is_none() {
+        return Some(format!("@{user} chat isn't being logged"));
+    }
+    let count = call
+        .args
+        .get(1)
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(DEFAULT_SEARCH_COUNT)
+        .clamp(1, MAX_SEARCH_COUNT);
+
+    let reply = match chat_log.search(&msg.channel_name, &login, count) {
+        Ok(entries) if entries.is_empty() => format!("@{user} no messages from {login}"),
+        Ok(entries) => {
+            let lines: Vec<_> = entries.iter().map(ToString::to_string).collect();
+            format!("@{user} {}", lines.join(" | "))
+        }
+        Err(err) => {
+            error!("Could not search the chat logs of #{}: {}", msg.channel_name, err);
+            format!("@{user} the logs couldn't be searched")
+        }
+    };
+
+    Some(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::twitch::chat_bot::json_file::TempPath;
+
+    #[test]
+    fn finds_latest_messages_of_a_user() {
+        let dir = TempPath::new("chat-log");
+        let mut chat_log = ChatLog {
+            dir: Some(dir.to_path_buf()),
+            keep_days: None,
+        };
+        let old = |channel: &str| {
+            let entry = LogEntry {
+                time: "2020-11-05T20:00:00+01:00".to_string(),
+                channel: channel.to_string(),
+                kind: LogKind::Message,
+                user: "bob".to_string(),
+                text: "first".to_string(),
+            };
+            append(&dir.join(channel).join("2020-11-05.jsonl"), &entry).unwrap();
+        };
+
+        // An old day that's searched too
+        old("neon");
+        chat_log.write("neon", LogKind::Message, "bob", "second");
+        chat_log.write("neon", LogKind::Moderation, "bob", "caps: /timeout bob 60");
+        chat_log.write("neon", LogKind::Message, "alice", "hi");
+        chat_log.write("neon", LogKind::Message, "bob", "third");
+        chat_log.write("neon", LogKind::Reply, "bot", "hello bob");
+
+        let texts = |chat_log: &ChatLog, channel, count| -> Vec<_> {
+            chat_log
+                .search(channel, "BOB", count)
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.text)
+                .collect()
+        };
+        assert_eq!(log_files(&dir.join("neon")).unwrap().len(), 2);
+        assert_eq!(texts(&chat_log, "neon", 2), ["second", "third"]);
+        assert_eq!(texts(&chat_log, "neon", 5), ["first", "second", "third"]);
+        assert!(chat_log.search("other", "bob", 5).unwrap().is_empty());
+
+        // With keep_days the old file is removed when today's is started
+        chat_log.keep_days = Some(7);
+        old("other");
+        chat_log.write("other", LogKind::Message, "bob", "second");
+        assert_eq!(texts(&chat_log, "other", 5), ["second"]);
+    }
+
+    #[test]
+    fn describes_timeouts_bans_and_deletions() {
+        let event = |line| moderation_event(&IrcMessage::parse(line).unwrap());
+
+        assert_eq!(
+            event("@ban-duration=600;room-id=1 :tmi.twitch.tv CLEARCHAT #neon :bob"),
+            Some(("bob".to_string(), "timed out for 600s".to_string()))
+        );
+        assert_eq!(
+            event("@room-id=1 :tmi.twitch.tv CLEARCHAT #neon :bob"),
+            Some(("bob".to_string(), "banned".to_string()))
+        );
+        assert_eq!(
+            event("@login=alice;target-msg-id=abc :tmi.twitch.tv CLEARMSG #neon :buy followers"),
+            Some(("alice".to_string(), "message deleted: buy followers".to_string()))
+        );
+        assert_eq!(event(":tmi.twitch.tv PRIVMSG #neon :hi"), None);
+    }
+}
@@ -1,32 +1,271 @@
+/// How commands are recognised in chat
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandStyle {
+    pub prefix: String,
+    pub case_sensitive: bool,
+}
+
+impl Default for CommandStyle {
+    fn default() -> Self {
+        Self {
+            prefix: "!".to_string(),
+            case_sensitive: false,
+        }
+    }
+}
+
+/// A command name and its arguments, `!so @bob` is `so` with the argument `@bob`
+#[derive(Debug, PartialEq, Eq)]
+pub struct CommandCall<'a> {
+    pub name: &'a str,
+    pub args: Vec<&'a str>,
+}
+
+impl CommandStyle {
+    /// Splits a chat message into a command and its arguments,
+    /// None if the message doesn't start with the prefix
+    #[must_use]
+    pub fn parse<'a>(&self, message: &'a str) -> Option<CommandCall<'a>> {
+        let mut words = message.trim().strip_prefix(self.prefix.as_str())?.split_whitespace();
+        let name = words.next()?;
+
+        Some(CommandCall {
+            name,
+            args: words.collect(),
+        })
+    }
+
+    /// Whether a command file trigger like `!discord` is the command called `name`
+    #[must_use]
+    pub fn matches(&self, trigger: &str, name: &str) -> bool {
+        trigger.strip_prefix(self.prefix.as_str()).is_some_and(|trigger| {
+            if self.case_sensitive {
+                trigger == name
+            } else {
+                trigger.to_lowercase() == name.to_lowercase()
+            }
+        })
+    }
+
+    /// The trigger for a command name, adding the prefix if it's missing
+    #[must_use]
+    pub fn trigger(&self, name: &str) -> String {
+        if name.starts_with(self.prefix.as_str()) {
+            name.to_string()
+        } else {
+            format!("{}{name}", self.prefix)
+        }
+    }
+}
+
 pub mod chat_commands {
+    use crate::twitch::chat_bot::chat_log::logs_command;
+    use crate::twitch::chat_bot::command_set::{ChatCommand, CommandSet, TriggerKind};
+    use crate::twitch::chat_bot::counters::{change_counter, counter_command, CounterChange};
+    use crate::twitch::chat_bot::quotes::quote_command;
+    use crate::twitch::chat_bot::raffle::raffle_command;
+    use crate::twitch::chat_bot::manage_commands::manage_commands;
+    use crate::twitch::chat_bot::moderation::permit_command;
     use crate::twitch::chat_bot::msg_parser::TwitchChatMsg;
-    use crate::twitch::chat_bot::send_msg::send_msg;
-
-    use config::Config;
-    use native_tls::TlsStream;
-    use std::collections::HashMap;
-    use std::net::TcpStream;
-    use tungstenite::stream::Stream;
-    use tungstenite::WebSocket;
+    use crate::twitch::chat_bot::permissions::Permission;
+    use crate::twitch::chat_bot::points::points_command;
+    use crate::twitch::chat_bot::polls::poll_command;
+    use crate::twitch::chat_bot::template::{render, TemplateVars};
+    use crate::twitch::chat_bot::TwidshTshadBott;
+
+    use super::{CommandCall, CommandStyle};
+
     use log::*;
 
     pub fn cmd_response(
-        msg: TwitchChatMsg,
-        socket: &mut WebSocket<Stream<TcpStream, TlsStream<TcpStream>>>,
-        commands: &Config,
+        msg: &TwitchChatMsg,
+        bot: &mut TwidshTshadBott,
+        commands: &mut CommandSet,
     ) {
+        info!("<{}>: {}", msg.display_name, msg.message);
+        let style = bot.command_style.clone();
+        let call = style.parse(&msg.message);
 
-        let parsed_commands = commands
-            .to_owned()
-            .try_into::<HashMap<String, String>>()
-            .expect("Could not parse commands file");
+        // Raffle entries, claims and votes can be any message, commands included
+        let points_name = &bot.points_settings.name;
+        if let Some(reply) = bot.raffles.chatted(msg, &mut bot.points, &bot.raffle_settings, points_name) {
+            bot.sender.queue(&msg.channel_name, &reply);
+        }
+        bot.polls.vote(msg);
 
-        info!("<{}>: {}", msg.display_name, msg.message);
-        for (command_key, command_res) in parsed_commands.iter() {
-            if msg.message.trim().eq(command_key) {
-                send_msg(socket, &msg.channel_name, command_res.to_string());
+        if let Some(call) = &call {
+            if let Some(reply) = manage_commands(msg, call, commands, &bot.commands_file, &style) {
+                bot.sender.queue(&msg.channel_name, &reply);
+                return;
+            }
+            let permission = bot.counter_permission;
+            if let Some(reply) = counter_command(msg, call, &mut bot.counters, &style, permission) {
+                bot.sender.queue(&msg.channel_name, &reply);
+                return;
+            }
+            let game = bot.stream_status.borrow().game(&msg.channel_name).map(ToString::to_string);
+            if let Some(reply) = quote_command(msg, call, &mut bot.quotes, &style, game.as_deref()) {
+                bot.sender.queue(&msg.channel_name, &reply);
+                return;
+            }
+            let links = bot.moderation.links();
+            if let Some(reply) = permit_command(msg, call, &mut bot.permits, &style, links) {
+                bot.sender.queue(&msg.channel_name, &reply);
+                return;
+            }
+            let points_settings = &bot.points_settings;
+            if let Some(reply) = points_command(msg, call, &mut bot.points, &style, points_settings) {
+                bot.sender.queue(&msg.channel_name, &reply);
+                return;
+            }
+            let points_name = &bot.points_settings.name;
+            let settings = &bot.raffle_settings;
+            if let Some(reply) = raffle_command(msg, call, &mut bot.raffles, &mut bot.points, &style, settings, points_name) {
+                bot.sender.queue(&msg.channel_name, &reply);
+                return;
+            }
+            if let Some(reply) = poll_command(msg, call, &mut bot.polls, &style, &bot.poll_settings) {
+                bot.sender.queue(&msg.channel_name, &reply);
+                return;
+            }
+            if let Some(reply) = logs_command(msg, call, &bot.chat_log, &style) {
+                bot.sender.queue(&msg.channel_name, &reply);
+                return;
+            }
+            if let Some(reply) = set_game(msg, call, bot, &style) {
+                bot.sender.queue(&msg.channel_name, &reply);
+                return;
+            }
+        }
+
+        // Triggers without the prefix still have to match the whole message
+        let found = call.as_ref().map_or_else(
+            || commands.find_exact(&msg.channel_name, msg.message.trim()),
+            |call| commands.find(&msg.channel_name, call.name, &style),
+        );
+        if let Some((trigger, command)) = found {
+            let args = call.map(|call| call.args).unwrap_or_default();
+            run_command(msg, bot, trigger, command, &args);
+        } else if let Some((trigger, command, groups)) =
+            commands.find_trigger(&msg.channel_name, &msg.message)
+        {
+            run_command(msg, bot, trigger, command, &groups);
+        }
+    }
+
+    /// `!setgame <game>` records what's being played, for quotes
+    fn set_game(
+        msg: &TwitchChatMsg,
+        call: &CommandCall<'_>,
+        bot: &TwidshTshadBott,
+        style: &CommandStyle,
+    ) -> Option<String> {
+        if !style.matches(&style.trigger("setgame"), call.name)
+            || Permission::of(msg) < Permission::Moderator
+        {
+            return None;
+        }
+
+        let game = call.args.join(" ");
+        bot.stream_status
+            .borrow_mut()
+            .set_game(&msg.channel_name, &game);
+
+        Some(if game.is_empty() {
+            format!("@{} cleared the game", msg.display_name)
+        } else {
+            format!("@{} the game is now {game}", msg.display_name)
+        })
+    }
+
+    fn run_command(
+        msg: &TwitchChatMsg,
+        bot: &mut TwidshTshadBott,
+        command_key: &str,
+        command: &ChatCommand,
+        args: &[&str],
+    ) {
+        let permission = Permission::of(msg);
+        if permission < command.permission {
+            info!(
+                "<{}> is not allowed to use {} ({:?} required)",
+                msg.display_name, command_key, command.permission
+            );
+            return;
+        }
+
+        // `!deaths +` changes the counter if the user may, anything else shows the response
+        let change = command
+            .counter
+            .as_ref()
+            .filter(|_| command.kind == TriggerKind::Command && permission >= bot.counter_permission)
+            .zip(CounterChange::parse(args));
+        if let Some((counter, change)) = change {
+            let reply = change_counter(msg, counter, change, &mut bot.counters);
+            bot.sender.queue(&msg.channel_name, &reply);
+            return;
+        }
+
+        let ignores_cooldown = command.mods_ignore_cooldown && permission >= Permission::Moderator;
+        if !ignores_cooldown {
+            if let Some(remaining) = bot.cooldowns.remaining(&msg.channel_name, command_key, &msg.login) {
+                info!(
+                    "{} is on cooldown for <{}>, {}s left",
+                    command_key,
+                    msg.display_name,
+                    remaining.as_secs() + 1
+                );
+                if command.whisper_cooldown {
+                    let whisper = format!(
+                        "/w {} {} is on cooldown for {}s",
+                        msg.login,
+                        command_key,
+                        remaining.as_secs() + 1
+                    );
+                    bot.sender.queue(&msg.channel_name, &whisper);
+                }
+                return;
             }
         }
 
+        bot.cooldowns
+            .used(&msg.channel_name, command_key, &msg.login, command);
+        let count = bot
+            .command_uses
+            .entry((msg.channel_name.clone(), command_key.to_string()))
+            .or_default();
+        *count += 1;
+
+        let vars = TemplateVars {
+            user: &msg.display_name,
+            channel: &msg.channel_name,
+            args,
+            count: *count,
+            uptime: bot.stream_status.borrow().uptime(&msg.channel_name),
+            counters: bot.counters.channel(&msg.channel_name),
+            extra: &[],
+        };
+        // Reply in whichever channel the command came from
+        let reply_to = Some(msg.id.as_str()).filter(|id| command.reply && !id.is_empty());
+        bot.sender
+            .queue_reply(&msg.channel_name, &render(&command.response, &vars), reply_to);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_command_and_args() {
+        let style = CommandStyle::default();
+        let call = style.parse("  !Send_Email bob@example.com now ").unwrap();
+
+        assert_eq!(call.name, "Send_Email");
+        assert_eq!(call.args, vec!["bob@example.com", "now"]);
+        assert!(style.matches("!send_email", call.name));
+        assert!(!style.matches("send_email", call.name));
+        assert!(style.parse("no command here").is_none());
+        assert!(style.parse("!").is_none());
     }
 }
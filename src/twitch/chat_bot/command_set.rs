@@ -0,0 +1,450 @@
+use config::Config;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use crate::twitch::chat_bot::command_parser::CommandStyle;
+use crate::twitch::chat_bot::json_file::save_atomic;
+use crate::twitch::chat_bot::permissions::Permission;
+use crate::twitch::chat_bot::template;
+
+/// A command response and who is allowed to trigger it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChatCommand {
+    pub response: String,
+    #[serde(default)]
+    pub permission: Permission,
+    /// Seconds before anyone can use the command again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cooldown: Option<u64>,
+    /// Seconds before the same user can use the command again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_cooldown: Option<u64>,
+    /// Moderators and the broadcaster ignore cooldowns
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub mods_ignore_cooldown: bool,
+    /// Whisper the user how long is left, instead of ignoring them
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub whisper_cooldown: bool,
+    /// Other names for the command, e.g. `["!dc"]` for `!discord`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    /// Answer in the thread of the message that used the command
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub reply: bool,
+    /// How the trigger is matched against chat messages
+    #[serde(rename = "match", default, skip_serializing_if = "TriggerKind::is_command")]
+    pub kind: TriggerKind,
+    /// Matched instead of the trigger by keywords and regexes,
+    /// for patterns with a `.`, which the commands file reads as nested keys
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    /// A counter the command changes when it's called with `+`, `-`, `set N` ...
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counter: Option<String>,
+}
+
+/// Commands are called with the prefix, keywords and regexes fire
+/// on any message that contains them
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TriggerKind {
+    #[default]
+    Command,
+    /// The trigger appears as a whole word or phrase, ignoring case
+    Keyword,
+    /// The trigger is a regex, capture groups are available as `{argN}`
+    Regex,
+}
+
+impl TriggerKind {
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    const fn is_command(&self) -> bool {
+        matches!(self, Self::Command)
+    }
+
+    /// The regex a trigger is matched with, None for commands
+    fn pattern(self, trigger: &str) -> Option<String> {
+        match self {
+            Self::Command => None,
+            Self::Keyword => Some(format!(r"(?i)\b{}\b", regex::escape(trigger))),
+            Self::Regex => Some(trigger.to_string()),
+        }
+    }
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+const fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+impl ChatCommand {
+    /// A command anyone can use
+    #[must_use]
+    pub fn new(response: &str) -> Self {
+        Self {
+            response: response.to_string(),
+            permission: Permission::default(),
+            cooldown: None,
+            user_cooldown: None,
+            mods_ignore_cooldown: false,
+            whisper_cooldown: false,
+            aliases: Vec::new(),
+            reply: false,
+            kind: TriggerKind::Command,
+            pattern: None,
+            counter: None,
+        }
+    }
+
+    /// The regex the command is matched with, None for commands
+    fn regex_pattern(&self, trigger: &str) -> Option<String> {
+        self.kind.pattern(self.pattern.as_deref().unwrap_or(trigger))
+    }
+}
+
+/// A command is either just its response, or a table with options
+/// `"!so": { "response": "...", "permission": "moderator" }`
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+enum CommandValue {
+    Response(String),
+    Command(ChatCommand),
+}
+
+impl From<CommandValue> for ChatCommand {
+    fn from(value: CommandValue) -> Self {
+        match value {
+            CommandValue::Response(response) => Self::new(&response),
+            CommandValue::Command(command) => command,
+        }
+    }
+}
+
+impl From<&ChatCommand> for CommandValue {
+    // Commands without options are written back as plain responses
+    fn from(command: &ChatCommand) -> Self {
+        if *command == ChatCommand::new(&command.response) {
+            Self::Response(command.response.clone())
+        } else {
+            Self::Command(command.clone())
+        }
+    }
+}
+
+/// An entry in the commands file, either a global command
+/// or a `#channel` table with commands only used in that channel
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+enum CommandEntry {
+    Command(CommandValue),
+    Channel(BTreeMap<String, CommandValue>),
+}
+
+/// Commands loaded from the commands file
+/// Channel specific commands override global ones with the same trigger
+#[derive(Debug, Default)]
+pub struct CommandSet {
+    pub global: HashMap<String, ChatCommand>,
+    pub channels: HashMap<String, HashMap<String, ChatCommand>>,
+    /// Compiled keyword and regex triggers, by pattern
+    patterns: HashMap<String, Regex>,
+}
+
+impl CommandSet {
+    /// # Errors
+    /// Returns an error if the commands file doesn't have the expected shape,
+    /// a trigger has a `.`, or a response uses an unknown `{variable}`
+    pub fn from_config(commands: &Config) -> Result<Self, config::ConfigError> {
+        let entries = commands
+            .clone()
+            .try_into::<HashMap<String, CommandEntry>>()?;
+
+        let mut command_set = Self::default();
+        for (key, entry) in entries {
+            match entry {
+                CommandEntry::Command(command) => {
+                    command_set.global.insert(key, command.into());
+                }
+                // `"gg.wp": {...}` is read as `"gg": { "wp": {...} }`, which would silently never match
+                CommandEntry::Channel(channel_commands) if !key.starts_with('#') => {
+                    let nested = channel_commands.keys().next().map_or(String::new(), |key| format!(".{key}"));
+                    return Err(config::ConfigError::Message(format!(
+                        "{key}{nested}: triggers can't contain a '.', \
+                         use a \"pattern\" for keywords and regexes with one"
+                    )));
+                }
+                CommandEntry::Channel(channel_commands) => {
+                    let channel = key.trim_start_matches('#').to_lowercase();
+                    let channel_commands = channel_commands
+                        .into_iter()
+                        .map(|(trigger, command)| (trigger, command.into()))
+                        .collect();
+                    command_set.channels.insert(channel, channel_commands);
+                }
+            }
+        }
+
+        command_set.validate()?;
+
+        Ok(command_set)
+    }
+
+    /// Checks the `{variables}` in every response and compiles keyword and regex triggers
+    fn validate(&mut self) -> Result<(), config::ConfigError> {
+        let mut patterns = HashMap::new();
+        let global = self.global.iter().map(|(trigger, command)| ("", trigger, command));
+        let channels = self.channels.iter().flat_map(|(channel, commands)| {
+            commands
+                .iter()
+                .map(move |(trigger, command)| (channel.as_str(), trigger, command))
+        });
+
+        for (channel, trigger, command) in global.chain(channels) {
+            let location = || {
+                if channel.is_empty() {
+                    trigger.clone()
+                } else {
+                    format!("#{channel} {trigger}")
+                }
+            };
+            if let Err(err) = template::validate(&command.response) {
+                return Err(config::ConfigError::Message(format!(
+                    "{}: {err}",
+                    location()
+                )));
+            }
+            if let Some(pattern) = command.regex_pattern(trigger) {
+                let regex = Regex::new(&pattern).map_err(|err| {
+                    config::ConfigError::Message(format!("{}: {err}", location()))
+                })?;
+                patterns.insert(pattern, regex);
+            }
+        }
+
+        self.patterns = patterns;
+        Ok(())
+    }
+
+    /// Commands only used in a channel, created if there are none yet
+    pub fn channel_mut(&mut self, channel: &str) -> &mut HashMap<String, ChatCommand> {
+        self.channels.entry(channel.to_lowercase()).or_default()
+    }
+
+    /// Writes the commands as JSON
+    /// # Errors
+    /// Returns an error if the file couldn't be written or moved into place
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut entries: BTreeMap<String, CommandEntry> = self
+            .global
+            .iter()
+            .map(|(trigger, command)| (trigger.clone(), CommandEntry::Command(command.into())))
+            .collect();
+
+        for (channel, channel_commands) in self.channels.iter().filter(|(_, c)| !c.is_empty()) {
+            let channel_commands = channel_commands
+                .iter()
+                .map(|(trigger, command)| (trigger.clone(), command.into()))
+                .collect();
+            entries.insert(format!("#{channel}"), CommandEntry::Channel(channel_commands));
+        }
+
+        save_atomic(path, &entries)
+    }
+
+    /// Finds the command a user called by name (or alias) in a channel
+    #[must_use]
+    pub fn find(
+        &self,
+        channel: &str,
+        name: &str,
+        style: &CommandStyle,
+    ) -> Option<(&str, &ChatCommand)> {
+        let mut commands: Vec<_> = self
+            .for_channel(channel)
+            .into_iter()
+            .filter(|(_, command)| command.kind == TriggerKind::Command)
+            .collect();
+        // Sorted so the same one wins every time when triggers only differ in case
+        commands.sort_by_key(|(trigger, _)| *trigger);
+
+        let exact = style.trigger(name);
+        commands
+            .iter()
+            .find(|(trigger, _)| *trigger == exact)
+            .or_else(|| commands.iter().find(|(trigger, _)| style.matches(trigger, name)))
+            .or_else(|| {
+                commands.iter().find(|(_, command)| {
+                    command
+                        .aliases
+                        .iter()
+                        .any(|alias| style.matches(&style.trigger(alias), name))
+                })
+            })
+            .map(|(trigger, command)| (*trigger, *command))
+    }
+
+    /// Finds a command whose trigger is the whole message
+    #[must_use]
+    pub fn find_exact(&self, channel: &str, message: &str) -> Option<(&str, &ChatCommand)> {
+        self.for_channel(channel)
+            .into_iter()
+            .find(|(trigger, command)| command.kind == TriggerKind::Command && *trigger == message)
+    }
+
+    /// Finds a keyword or regex trigger in a message, along with the regex capture groups
+    ///
+    /// Triggers are tried in alphabetical order so the same one wins every time
+    #[must_use]
+    pub fn find_trigger<'a>(
+        &self,
+        channel: &str,
+        message: &'a str,
+    ) -> Option<(&str, &ChatCommand, Vec<&'a str>)> {
+        let mut triggers: Vec<_> = self
+            .for_channel(channel)
+            .into_iter()
+            .filter_map(|(trigger, command)| {
+                let pattern = command.regex_pattern(trigger)?;
+                Some((trigger, command, self.patterns.get(&pattern)?))
+            })
+            .collect();
+        triggers.sort_unstable_by_key(|(trigger, _, _)| *trigger);
+
+        triggers.into_iter().find_map(|(trigger, command, regex)| {
+            let captures = regex.captures(message)?;
+            let groups = captures
+                .iter()
+                .skip(1)
+                .map(|group| group.map_or("", |group| group.as_str()))
+                .collect();
+            Some((trigger, command, groups))
+        })
+    }
+
+    /// The trigger of a command defined in a channel's own table, found by name
+    #[must_use]
+    pub fn channel_trigger(&self, channel: &str, name: &str, style: &CommandStyle) -> Option<String> {
+        self.channels
+            .get(channel)?
+            .keys()
+            .find(|trigger| style.matches(trigger, name))
+            .cloned()
+    }
+
+    /// All commands available in a channel
+    #[must_use]
+    pub fn for_channel(&self, channel: &str) -> HashMap<&str, &ChatCommand> {
+        let mut commands: HashMap<&str, &ChatCommand> = self
+            .global
+            .iter()
+            .map(|(key, command)| (key.as_str(), command))
+            .collect();
+
+        if let Some(channel_commands) = self.channels.get(channel) {
+            commands.extend(
+                channel_commands
+                    .iter()
+                    .map(|(key, command)| (key.as_str(), command)),
+            );
+        }
+
+        commands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::twitch::chat_bot::json_file::TempPath;
+    use config::{File, FileFormat};
+
+    fn command_set(json: &str) -> CommandSet {
+        let mut config = Config::default();
+        config.merge(File::from_str(json, FileFormat::Json)).unwrap();
+        CommandSet::from_config(&config).unwrap()
+    }
+
+    #[test]
+    fn finds_keyword_and_regex_triggers() {
+        let commands = command_set(
+            r#"{
+                "i command you": { "response": "No", "match": "keyword" },
+                "^good (morning|night)": { "response": "Good {arg1}", "match": "regex" },
+                "!lurk": "Enjoy the lurk"
+            }"#,
+        );
+
+        let (trigger, _, groups) = commands.find_trigger("neon", "I COMMAND YOU to stop").unwrap();
+        assert_eq!(trigger, "i command you");
+        assert!(groups.is_empty());
+        assert!(commands.find_trigger("neon", "hi command yourself").is_none());
+
+        let (_, command, groups) = commands.find_trigger("neon", "good night all").unwrap();
+        assert_eq!(command.response, "Good {arg1}");
+        assert_eq!(groups, vec!["night"]);
+        assert!(commands.find_trigger("neon", "!lurk").is_none());
+        assert!(commands.find_exact("neon", "i command you").is_none());
+    }
+
+    #[test]
+    fn exact_trigger_wins_over_other_case() {
+        let mut commands = CommandSet::default();
+        commands.global.insert("!Foo".to_string(), ChatCommand::new("upper"));
+        commands.global.insert("!foo".to_string(), ChatCommand::new("lower"));
+        let style = CommandStyle::default();
+
+        assert_eq!(commands.find("neon", "foo", &style).unwrap().1.response, "lower");
+        assert_eq!(commands.find("neon", "Foo", &style).unwrap().1.response, "upper");
+        assert_eq!(commands.find("neon", "FOO", &style).unwrap().0, "!Foo");
+    }
+
+    #[test]
+    fn saved_commands_load_the_same() {
+        let commands = command_set(
+            r##"{
+                "!discord": "The discord is awesome!!",
+                "!so": { "response": "Go follow {touser}", "permission": "moderator", "aliases": ["!shoutout"] },
+                "gg": { "response": "GG!", "match": "keyword", "pattern": "gg.wp", "cooldown": 30 },
+                "#neonraytracer": {
+                    "!discord": "Join the NEON discord!",
+                    "!deaths": { "response": "Died {counter:deaths} times", "counter": "deaths", "reply": true }
+                }
+            }"##,
+        );
+        let path = TempPath::new("commands.json");
+        commands.save(&path).unwrap();
+
+        let reloaded = crate::hot_reload::load_commands(&path).unwrap();
+        assert_eq!(reloaded.global, commands.global);
+        assert_eq!(reloaded.channels, commands.channels);
+        // Commands without options are written as plain responses
+        assert!(std::fs::read_to_string(&path).unwrap().contains(r#""!discord": "The discord is awesome!!""#));
+    }
+
+    #[test]
+    fn invalid_regex_is_a_config_error() {
+        let mut config = Config::default();
+        config
+            .merge(File::from_str(r#"{ "(oops": { "response": "x", "match": "regex" } }"#, FileFormat::Json))
+            .unwrap();
+        assert!(CommandSet::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn dotted_triggers_are_a_config_error() {
+        let mut config = Config::default();
+        config
+            .merge(File::from_str(r#"{ "gg.wp": { "response": "x", "match": "keyword" } }"#, FileFormat::Json))
+            .unwrap();
+        let err = CommandSet::from_config(&config).unwrap_err().to_string();
+        assert!(err.contains("gg.wp"), "{err}");
+
+        // The pattern can have dots when it's a value
+        let commands = command_set(r#"{ "gg": { "response": "x", "match": "keyword", "pattern": "gg.wp" } }"#);
+        let (trigger, _, _) = commands.find_trigger("neon", "gg.wp everyone").unwrap();
+        assert_eq!(trigger, "gg");
+        assert!(commands.find_trigger("neon", "gg everyone").is_none());
+    }
+}
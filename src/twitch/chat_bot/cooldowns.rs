@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::twitch::chat_bot::command_set::ChatCommand;
+
+/// When commands can be used again, per channel and per user
+///
+/// Only running cooldowns are kept, expired ones are dropped whenever a command is used
+#[derive(Debug, Default)]
+pub struct Cooldowns {
+    global: HashMap<(String, String), Instant>,
+    users: HashMap<(String, String, String), Instant>,
+}
+
+impl Cooldowns {
+    /// Time left before a user may use a command again, None if it's ready
+    #[must_use]
+    pub fn remaining(&self, channel: &str, trigger: &str, login: &str) -> Option<Duration> {
+        let now = Instant::now();
+        let global = self
+            .global
+            .get(&(channel.to_string(), trigger.to_string()))
+            .and_then(|until| until.checked_duration_since(now));
+        let user = self
+            .users
+            .get(&(channel.to_string(), trigger.to_string(), login.to_string()))
+            .and_then(|until| until.checked_duration_since(now));
+
+        global.max(user).filter(|remaining| !remaining.is_zero())
+    }
+
+    /// Starts the cooldowns of a command that was just used
+    pub fn used(&mut self, channel: &str, trigger: &str, login: &str, command: &ChatCommand) {
+        let now = Instant::now();
+        self.global.retain(|_, until| *until > now);
+        self.users.retain(|_, until| *until > now);
+
+        let until = |cooldown: Option<u64>| cooldown.and_then(|seconds| now.checked_add(Duration::from_secs(seconds)));
+        if let Some(until) = until(command.cooldown) {
+            self.global.insert((channel.to_string(), trigger.to_string()), until);
+        }
+        if let Some(until) = until(command.user_cooldown) {
+            self.users
+                .insert((channel.to_string(), trigger.to_string(), login.to_string()), until);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expired_cooldowns_are_dropped() {
+        let mut cooldowns = Cooldowns::default();
+        let mut command = ChatCommand::new("pong");
+        command.user_cooldown = Some(60);
+        cooldowns.used("neon", "!ping", "bob", &command);
+        assert!(cooldowns.remaining("neon", "!ping", "bob").is_some());
+        assert!(cooldowns.remaining("neon", "!ping", "alice").is_none());
+
+        // A cooldown of 0 is over right away, it's dropped with the next use
+        command.user_cooldown = Some(0);
+        cooldowns.used("neon", "!other", "alice", &command);
+        std::thread::sleep(Duration::from_millis(1));
+        cooldowns.used("neon", "!other", "carol", &command);
+        assert!(cooldowns.remaining("neon", "!other", "alice").is_none());
+        assert_eq!(cooldowns.users.len(), 2);
+        assert!(cooldowns.global.is_empty());
+    }
+}
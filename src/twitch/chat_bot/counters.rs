@@ -0,0 +1,206 @@
+use log::{error, info};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::twitch::chat_bot::command_parser::{CommandCall, CommandStyle};
+use crate::twitch::chat_bot::json_file::{load_or_default, save_atomic};
+use crate::twitch::chat_bot::msg_parser::TwitchChatMsg;
+use crate::twitch::chat_bot::permissions::Permission;
+
+/// Named counters per channel, like deaths or bugs found on stream
+///
+/// Every change is written to the counters file so they survive a restart
+#[derive(Debug, Default)]
+pub struct Counters {
+    path: PathBuf,
+    counts: BTreeMap<String, BTreeMap<String, i64>>,
+}
+
+impl Counters {
+    /// Reads the counters file
+    /// # Errors
+    /// Returns an error if the file exists but can't be read or parsed
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let counts = load_or_default(&path)?;
+
+        Ok(Self { path, counts })
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Value of a counter, counters that were never changed are 0
+    #[must_use]
+    pub fn get(&self, channel: &str, name: &str) -> i64 {
+        self.channel(channel)
+            .and_then(|counts| counts.get(&name.to_lowercase()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// All counters of a channel
+    #[must_use]
+    pub fn channel(&self, channel: &str) -> Option<&BTreeMap<String, i64>> {
+        self.counts.get(channel)
+    }
+
+    /// Sets a counter and saves the counters file
+    /// # Errors
+    /// Returns an error if the counters file couldn't be written
+    pub fn set(&mut self, channel: &str, name: &str, value: i64) -> io::Result<()> {
+        self.counts
+            .entry(channel.to_string())
+            .or_default()
+            .insert(name.to_lowercase(), value);
+
+        save_atomic(&self.path, &self.counts)
+    }
+}
+
+/// A change to a counter, from `+`, `-`, `+N`, `-N`, `set N` or `reset`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CounterChange {
+    Add(i64),
+    Set(i64),
+}
+
+impl CounterChange {
+    /// The change the arguments ask for, None if they aren't one
+    #[must_use]
+    pub fn parse(args: &[&str]) -> Option<Self> {
+        match *args {
+            ["+"] => Some(Self::Add(1)),
+            ["-"] => Some(Self::Add(-1)),
+            ["reset"] => Some(Self::Set(0)),
+            ["set", value] => value.parse().ok().map(Self::Set),
+            [amount] if amount.starts_with(['+', '-']) => amount.parse().ok().map(Self::Add),
+            _ => None,
+        }
+    }
+
+    const fn apply(self, current: i64) -> i64 {
+        match self {
+            Self::Add(amount) => current.saturating_add(amount),
+            Self::Set(value) => value,
+        }
+    }
+}
+
+/// Handles `!counter <name> [+N | -N | set N | reset]`
+///
+/// Anyone can see a counter, changing it needs `permission`
+/// Returns the reply for chat, or None if the message wasn't a counter command
+pub fn counter_command(
+    msg: &TwitchChatMsg,
+    call: &CommandCall<'_>,
+    counters: &mut Counters,
+    style: &CommandStyle,
+    permission: Permission,
+) -> Option<String> {
+    if !style.matches(&style.trigger("counter"), call.name) {
+        return None;
+    }
+
+    let user = &msg.display_name;
+    let Some((name, args)) = call.args.split_first() else {
+        return Some(format!(
+            "@{user} usage: {}counter <name> [+ | - | set <number> | reset]",
+            style.prefix
+        ));
+    };
+    let name = name.to_lowercase();
+    if args.is_empty() {
+        return Some(format!("{name}: {}", counters.get(&msg.channel_name, &name)));
+    }
+
+    if Permission::of(msg) < permission {
+        info!("<{}> tried to change counter {} without permission", user, name);
+        return None;
+    }
+    let Some(change) = CounterChange::parse(args) else {
+        return Some(format!(
+            "@{user} usage: {}counter {name} [+ | - | +N | -N | set <number> | reset]",
+            style.prefix
+        ));
+    };
+
+    Some(change_counter(msg, &name, change, counters))
+}
+
+/// Changes a counter and saves it, for `!counter` and commands with a `counter`, like `!deaths +`
+///
+/// Returns the reply for chat
+pub fn change_counter(msg: &TwitchChatMsg, name: &str, change: CounterChange, counters: &mut Counters) -> String {
+    let channel = &msg.channel_name;
+    let name = name.to_lowercase();
+    let current = counters.get(channel, &name);
+    let value = change.apply(current);
+
+    info!("<{}> in #{}: counter {} {} -> {}", msg.display_name, channel, name, current, value);
+    if let Err(err) = counters.set(channel, &name, value) {
+        error!(
+            "Could not save counters to {}: {}",
+            counters.path().display(),
+            err
+        );
+        return format!("{name}: {value}, but it couldn't be saved and will be lost on restart");
+    }
+
+    format!("{name}: {value}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::twitch::chat_bot::json_file::TempPath;
+
+    #[test]
+    fn moderators_change_counters() {
+        let path = TempPath::new("counters.json");
+        let mut counters = Counters::load(path.to_path_buf()).unwrap();
+        let style = CommandStyle::default();
+        let mut command = |badges, message| {
+            let msg = TwitchChatMsg::for_test("neon", badges, "Bob", message);
+            let call = style.parse(&msg.message).unwrap();
+            counter_command(&msg, &call, &mut counters, &style, Permission::Moderator)
+        };
+
+        assert_eq!(command("", "!counter Deaths").unwrap(), "deaths: 0");
+        assert!(command("", "!counter deaths +").is_none());
+        assert_eq!(command("moderator/1", "!counter deaths +").unwrap(), "deaths: 1");
+        assert_eq!(command("moderator/1", "!counter deaths +5").unwrap(), "deaths: 6");
+        assert_eq!(command("moderator/1", "!counter deaths -").unwrap(), "deaths: 5");
+        assert_eq!(command("moderator/1", "!counter deaths -2").unwrap(), "deaths: 3");
+        assert_eq!(command("broadcaster/1", "!counter deaths set 10").unwrap(), "deaths: 10");
+        assert!(command("moderator/1", "!counter deaths set ten").unwrap().starts_with("@Bob usage: !counter deaths"));
+        assert_eq!(command("", "!counter deaths").unwrap(), "deaths: 10");
+        assert_eq!(command("moderator/1", "!counter deaths reset").unwrap(), "deaths: 0");
+        assert!(command("", "!deaths").is_none());
+        // Only people who may change counters are told how to
+        assert!(command("", "!counter deaths lol").is_none());
+        assert!(command("moderator/1", "!counter deaths lol").unwrap().starts_with("@Bob usage:"));
+
+        // Commands with a counter change it through their own trigger
+        let msg = TwitchChatMsg::for_test("neon", "vip/1", "Bob", "!deaths +");
+        assert_eq!(change_counter(&msg, "Deaths", CounterChange::Add(1), &mut counters), "deaths: 1");
+
+        let reloaded = Counters::load(path.to_path_buf()).unwrap();
+        assert_eq!(reloaded.get("neon", "deaths"), 1);
+    }
+
+    #[test]
+    fn only_actions_are_counter_changes() {
+        assert_eq!(CounterChange::parse(&["+"]), Some(CounterChange::Add(1)));
+        assert_eq!(CounterChange::parse(&["-3"]), Some(CounterChange::Add(-3)));
+        assert_eq!(CounterChange::parse(&["set", "10"]), Some(CounterChange::Set(10)));
+        assert_eq!(CounterChange::parse(&["reset"]), Some(CounterChange::Set(0)));
+        // `!deaths lol so many` shows the response instead
+        assert_eq!(CounterChange::parse(&["lol", "so", "many"]), None);
+        assert_eq!(CounterChange::parse(&["+", "one"]), None);
+        assert_eq!(CounterChange::parse(&["set", "ten"]), None);
+        assert_eq!(CounterChange::parse(&[]), None);
+    }
+}
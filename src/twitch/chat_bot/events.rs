@@ -0,0 +1,338 @@
+use log::{error, info};
+use serde::Deserialize;
+use std::process::Command;
+use std::thread;
+
+use crate::twitch::chat_bot::msg_parser::IrcMessage;
+use crate::twitch::chat_bot::template::{self, render, TemplateVars};
+
+/// Variables event messages can use besides the usual ones, empty if an event doesn't have them
+pub const EVENT_VARS: [&str; 7] = ["months", "plan", "recipient", "gifts", "viewers", "ritual", "message"];
+
+/// What to do when an event happens, from the `events` setting
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct EventAction {
+    /// Sent to chat, can use the usual variables and the event's own
+    pub message: Option<String>,
+    /// Show a desktop notification
+    pub notify: bool,
+    /// A program and its arguments to run, the arguments can use the same variables as the message
+    pub run: Vec<String>,
+}
+
+impl EventAction {
+    /// Checks the variables in the message and program arguments
+    /// # Errors
+    /// Returns a description of the first unknown variable
+    pub fn validate(&self, extra: &[&str]) -> Result<(), String> {
+        self.message
+            .iter()
+            .chain(self.run.iter().skip(1))
+            .try_for_each(|template| template::validate_with(template, extra))
+    }
+
+    /// Starts the program in `run` without waiting for it
+    pub fn run_program(&self, vars: &TemplateVars<'_>) {
+        let Some((program, args)) = self.run.split_first() else {
+            return;
+        };
+        let args: Vec<_> = args.iter().map(|arg| render(arg, vars)).collect();
+
+        match Command::new(program).args(&args).spawn() {
+            Ok(mut child) => {
+                info!("Running {} {}", program, args.join(" "));
+                // Reap it once it's done, so it doesn't linger as a zombie
+                thread::spawn(move || child.wait());
+            }
+            Err(err) => error!("Could not run {}: {}", program, err),
+        }
+    }
+}
+
+/// Variables cheer messages can use besides the usual ones
+pub const CHEER_VARS: [&str; 3] = ["bits", "total_bits", "message"];
+
+/// What to do for cheers of at least `min_bits`, from the `cheers` setting
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CheerAction {
+    pub min_bits: u64,
+    #[serde(flatten)]
+    pub action: EventAction,
+}
+
+/// The action of the highest threshold a cheer reaches
+#[must_use]
+pub fn cheer_action(cheers: &[CheerAction], bits: u64) -> Option<&EventAction> {
+    cheers
+        .iter()
+        .filter(|cheer| cheer.min_bits <= bits)
+        .max_by_key(|cheer| cheer.min_bits)
+        .map(|cheer| &cheer.action)
+}
+
+/// Subs, raids and other USERNOTICEs, by their `msg-id`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChatEvent {
+    Sub {
+        user: String,
+        plan: String,
+        message: String,
+    },
+    Resub {
+        user: String,
+        months: u64,
+        plan: String,
+        message: String,
+    },
+    SubGift {
+        gifter: String,
+        recipient: String,
+        months: u64,
+        plan: String,
+    },
+    MysteryGift {
+        gifter: String,
+        gifts: u64,
+        plan: String,
+    },
+    Raid {
+        raider: String,
+        viewers: u64,
+    },
+    Ritual {
+        user: String,
+        ritual: String,
+        message: String,
+    },
+    Announcement {
+        user: String,
+        message: String,
+    },
+}
+
+/// Names of the events in the `events` setting
+pub const EVENT_KINDS: [&str; 7] = [
+    "sub",
+    "resub",
+    "subgift",
+    "submysterygift",
+    "raid",
+    "ritual",
+    "announcement",
+];
+
+impl ChatEvent {
+    /// Parses a USERNOTICE, None if its `msg-id` isn't one we know
+    #[must_use]
+    pub fn parse(notice: &IrcMessage) -> Option<Self> {
+        let tag = |key: &str| notice.tag(key).unwrap_or_default().to_string();
+        let number = |key: &str| notice.tag(key).and_then(|value| value.parse().ok()).unwrap_or_default();
+        let user = notice
+            .tag("display-name")
+            .or_else(|| notice.tag("login"))
+            .unwrap_or_default()
+            .to_string();
+        let plan = sub_plan(notice.tag("msg-param-sub-plan"));
+        // The message the user sent along, if any
+        let message = if notice.params.len() > 1 {
+            notice.trailing().unwrap_or_default().to_string()
+        } else {
+            String::new()
+        };
+
+        let event = match notice.tag("msg-id")? {
+            "sub" => Self::Sub { user, plan, message },
+            "resub" => Self::Resub {
+                user,
+                months: number("msg-param-cumulative-months"),
+                plan,
+                message,
+            },
+            // Every gift of a mystery gift has its own notice, the mystery gift already thanks for them
+            "subgift" | "anonsubgift" if notice.tag("msg-param-community-gift-id").is_some() => return None,
+            "subgift" | "anonsubgift" => Self::SubGift {
+                gifter: user,
+                recipient: tag("msg-param-recipient-display-name"),
+                months: number("msg-param-months"),
+                plan,
+            },
+            "submysterygift" | "anonsubmysterygift" => Self::MysteryGift {
+                gifter: user,
+                gifts: number("msg-param-mass-gift-count"),
+                plan,
+            },
+            "raid" => Self::Raid {
+                raider: notice.tag("msg-param-displayName").map_or(user, ToString::to_string),
+                viewers: number("msg-param-viewerCount"),
+            },
+            "ritual" => Self::Ritual {
+                user,
+                ritual: tag("msg-param-ritual-name"),
+                message,
+            },
+            "announcement" => Self::Announcement { user, message },
+            _ => return None,
+        };
+
+        Some(event)
+    }
+
+    /// The event's name in the `events` setting
+    #[must_use]
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::Sub { .. } => "sub",
+            Self::Resub { .. } => "resub",
+            Self::SubGift { .. } => "subgift",
+            Self::MysteryGift { .. } => "submysterygift",
+            Self::Raid { .. } => "raid",
+            Self::Ritual { .. } => "ritual",
+            Self::Announcement { .. } => "announcement",
+        }
+    }
+
+    /// Who subbed, gifted, raided or announced
+    #[must_use]
+    pub fn user(&self) -> &str {
+        match self {
+            Self::Sub { user, .. }
+            | Self::Resub { user, .. }
+            | Self::Ritual { user, .. }
+            | Self::Announcement { user, .. } => user,
+            Self::SubGift { gifter, .. } | Self::MysteryGift { gifter, .. } => gifter,
+            Self::Raid { raider, .. } => raider,
+        }
+    }
+
+    /// Values of the `EVENT_VARS` for templates
+    #[must_use]
+    pub fn vars(&self) -> Vec<(&'static str, String)> {
+        let mut vars: Vec<_> = EVENT_VARS.iter().map(|name| (*name, String::new())).collect();
+        let mut set = |name, value: String| {
+            if let Some(var) = vars.iter_mut().find(|(var, _)| *var == name) {
+                var.1 = value;
+            }
+        };
+
+        match self {
+            Self::Sub { plan, message, .. } => {
+                set("plan", plan.clone());
+                set("message", message.clone());
+            }
+            Self::Resub {
+                months,
+                plan,
+                message,
+                ..
+            } => {
+                set("months", months.to_string());
+                set("plan", plan.clone());
+                set("message", message.clone());
+            }
+            Self::SubGift {
+                recipient,
+                months,
+                plan,
+                ..
+            } => {
+                set("recipient", recipient.clone());
+                set("months", months.to_string());
+                set("plan", plan.clone());
+            }
+            Self::MysteryGift { gifts, plan, .. } => {
+                set("gifts", gifts.to_string());
+                set("plan", plan.clone());
+            }
+            Self::Raid { viewers, .. } => set("viewers", viewers.to_string()),
+            Self::Ritual { ritual, message, .. } => {
+                set("ritual", ritual.clone());
+                set("message", message.clone());
+            }
+            Self::Announcement { message, .. } => set("message", message.clone()),
+        }
+
+        vars
+    }
+}
+
+/// `1000` is tier 1 and so on, `Prime` stays as it is
+fn sub_plan(plan: Option<&str>) -> String {
+    match plan {
+        Some("1000") => "Tier 1".to_string(),
+        Some("2000") => "Tier 2".to_string(),
+        Some("3000") => "Tier 3".to_string(),
+        Some(plan) => plan.to_string(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_resubs_and_raids() {
+        let resub = IrcMessage::parse(
+            "@display-name=Bob;login=bob;msg-id=resub;msg-param-cumulative-months=6;msg-param-sub-plan=1000 \
+             :tmi.twitch.tv USERNOTICE #neonraytracer :Great stream!",
+        )
+        .unwrap();
+        let event = ChatEvent::parse(&resub).unwrap();
+        assert_eq!(
+            event,
+            ChatEvent::Resub {
+                user: "Bob".to_string(),
+                months: 6,
+                plan: "Tier 1".to_string(),
+                message: "Great stream!".to_string(),
+            }
+        );
+        assert!(event.vars().contains(&("months", "6".to_string())));
+
+        let raid = IrcMessage::parse(
+            "@display-name=Alice;login=alice;msg-id=raid;msg-param-displayName=Alice;msg-param-viewerCount=42 \
+             :tmi.twitch.tv USERNOTICE #neonraytracer",
+        )
+        .unwrap();
+        let event = ChatEvent::parse(&raid).unwrap();
+        assert_eq!(event.kind(), "raid");
+        assert_eq!(event.user(), "Alice");
+        assert!(event.vars().contains(&("message", String::new())));
+    }
+
+    #[test]
+    fn gifts_of_a_mystery_gift_are_skipped() {
+        let gift = |community_gift: &str| {
+            IrcMessage::parse(&format!(
+                "@display-name=Bob;login=bob;msg-id=subgift;{community_gift}msg-param-months=1;\
+                 msg-param-recipient-display-name=Alice;msg-param-sub-plan=1000 \
+                 :tmi.twitch.tv USERNOTICE #neonraytracer"
+            ))
+            .unwrap()
+        };
+
+        let event = ChatEvent::parse(&gift("")).unwrap();
+        assert_eq!(event.kind(), "subgift");
+        assert!(event.vars().contains(&("recipient", "Alice".to_string())));
+        assert!(ChatEvent::parse(&gift("msg-param-community-gift-id=123456;")).is_none());
+    }
+
+    #[test]
+    fn highest_cheer_threshold_wins() {
+        let cheer = |min_bits, message: &str| CheerAction {
+            min_bits,
+            action: EventAction {
+                message: Some(message.to_string()),
+                ..EventAction::default()
+            },
+        };
+        let cheers = [cheer(1000, "big"), cheer(1, "small"), cheer(100, "medium")];
+
+        let message = |bits| cheer_action(&cheers, bits).and_then(|action| action.message.as_deref());
+        assert_eq!(message(1), Some("small"));
+        assert_eq!(message(999), Some("medium"));
+        assert_eq!(message(5000), Some("big"));
+        assert_eq!(message(0), None);
+    }
+}
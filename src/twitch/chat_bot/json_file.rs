@@ -0,0 +1,87 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Reads a JSON file, a missing file is the default, like no counters or quotes yet
+/// # Errors
+/// Returns an error if the file exists but can't be read or parsed
+pub fn load_or_default<T: DeserializeOwned + Default>(path: &Path) -> io::Result<T> {
+    match fs::read_to_string(path) {
+        Ok(json) => Ok(serde_json::from_str(&json)?),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Writes a value as pretty JSON via a temporary file, so a crash can't leave the file half written
+/// # Errors
+/// Returns an error if the file couldn't be written or moved into place
+pub fn save_atomic<T: Serialize + ?Sized>(path: &Path, value: &T) -> io::Result<()> {
+    let json = serde_json::to_string_pretty(value)?;
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, json + "\n")?;
+    fs::rename(&tmp_path, path)
+}
+
+/// A file or directory in the temp dir for a test, removed when dropped, even if the test fails
+#[cfg(test)]
+pub struct TempPath(std::path::PathBuf);
+
+#[cfg(test)]
+impl TempPath {
+    /// `name` has to be unique among the tests, they run at the same time
+    #[must_use]
+    pub fn new(name: &str) -> Self {
+        Self(std::env::temp_dir().join(format!("twitch-bot-test-{}-{name}", std::process::id())))
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        if self.0.is_dir() {
+            let _ = fs::remove_dir_all(&self.0);
+        } else {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn missing_files_are_empty_and_saves_round_trip() {
+        let path = TempPath::new("json-file.json");
+        let empty: BTreeMap<String, u64> = load_or_default(&path).unwrap();
+        assert!(empty.is_empty());
+
+        let values = BTreeMap::from([("deaths".to_string(), 3_u64)]);
+        save_atomic(&path, &values).unwrap();
+        assert_eq!(load_or_default::<BTreeMap<String, u64>>(&path).unwrap(), values);
+        assert!(!path.with_extension("json.tmp").exists());
+
+        fs::write(&path, "not json").unwrap();
+        assert!(load_or_default::<BTreeMap<String, u64>>(&path).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use log::{error, info};
+use std::path::Path;
+
+use crate::twitch::chat_bot::command_parser::{CommandCall, CommandStyle};
+use crate::twitch::chat_bot::command_set::{ChatCommand, CommandSet};
+use crate::twitch::chat_bot::msg_parser::TwitchChatMsg;
+use crate::twitch::chat_bot::permissions::Permission;
+use crate::twitch::chat_bot::template;
+
+/// Handles `!addcom`, `!editcom`, `!renamecom` and `!delcom` from moderators
+///
+/// Changes apply to the channel the message came from and are saved right away
+/// Returns the reply for chat, or None if the message wasn't a management command
+pub fn manage_commands(
+    msg: &TwitchChatMsg,
+    call: &CommandCall<'_>,
+    commands: &mut CommandSet,
+    commands_file: &Path,
+    style: &CommandStyle,
+) -> Option<String> {
+    let action = ["addcom", "editcom", "renamecom", "delcom"]
+        .iter()
+        .copied()
+        .find(|action| style.matches(&style.trigger(action), call.name))?;
+
+    if Permission::of(msg) < Permission::Moderator {
+        info!("<{}> tried to use {} without permission", msg.display_name, call.name);
+        return None;
+    }
+
+    let user = &msg.display_name;
+    let channel = &msg.channel_name;
+    let usage = |args: &str| Some(format!("@{user} usage: {}{action} {args}", style.prefix));
+
+    let Some(name) = call.args.first() else {
+        return usage("<command> ...");
+    };
+    let name = name.trim_start_matches(style.prefix.as_str());
+    let trigger = style.trigger(name);
+    let rest = call.args[1..].join(" ");
+
+    let reply = match action {
+        "addcom" => {
+            if rest.is_empty() {
+                return usage("<command> <response>");
+            }
+            if let Err(err) = template::validate(&rest) {
+                return Some(format!("@{user} {trigger} not saved, {err}"));
+            }
+            if commands.find(channel, name, style).is_some() {
+                return Some(format!("@{user} {trigger} already exists, use {}editcom", style.prefix));
+            }
+            commands
+                .channel_mut(channel)
+                .insert(trigger.clone(), ChatCommand::new(&rest));
+            format!("@{user} added {trigger}")
+        }
+        "editcom" => {
+            if rest.is_empty() {
+                return usage("<command> <response>");
+            }
+            if let Err(err) = template::validate(&rest) {
+                return Some(format!("@{user} {trigger} not saved, {err}"));
+            }
+            // Editing a global command creates an override for this channel
+            let Some((existing, command)) = commands.find(channel, name, style) else {
+                return Some(format!("@{user} {trigger} doesn't exist"));
+            };
+            let (existing, mut command) = (existing.to_string(), command.clone());
+            command.response = rest;
+            commands.channel_mut(channel).insert(existing, command);
+            format!("@{user} edited {trigger}")
+        }
+        "renamecom" => {
+            let new_name = rest.trim_start_matches(style.prefix.as_str());
+            if new_name.is_empty() || new_name.contains(' ') {
+                return usage("<command> <new name>");
+            }
+            let new_trigger = style.trigger(new_name);
+            if commands.find(channel, new_name, style).is_some() {
+                return Some(format!("@{user} {new_trigger} already exists"));
+            }
+            let Some(existing) = commands.channel_trigger(channel, name, style) else {
+                return Some(not_in_channel(user, &trigger, commands));
+            };
+            if let Some(command) = commands.channel_mut(channel).remove(&existing) {
+                commands
+                    .channel_mut(channel)
+                    .insert(new_trigger.clone(), command);
+            }
+            format!("@{user} renamed {trigger} to {new_trigger}")
+        }
+        _ => {
+            let Some(existing) = commands.channel_trigger(channel, name, style) else {
+                return Some(not_in_channel(user, &trigger, commands));
+            };
+            commands.channel_mut(channel).remove(&existing);
+            format!("@{user} deleted {trigger}")
+        }
+    };
+
+    info!("<{}> in #{}: {}", msg.display_name, channel, msg.message.trim());
+    if let Err(err) = commands.save(commands_file) {
+        error!(
+            "Could not save commands to {}: {}",
+            commands_file.display(),
+            err
+        );
+        return Some(format!("{reply}, but it couldn't be saved and will be lost on restart"));
+    }
+
+    Some(reply)
+}
+
+fn not_in_channel(user: &str, trigger: &str, commands: &CommandSet) -> String {
+    if commands.global.contains_key(trigger) {
+        format!("@{user} {trigger} is a global command, it can only be changed in the commands file")
+    } else {
+        format!("@{user} {trigger} doesn't exist")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::twitch::chat_bot::json_file::TempPath;
+
+    #[test]
+    fn moderators_manage_channel_commands() {
+        let path = TempPath::new("manage-commands.json");
+        let mut commands = CommandSet::default();
+        commands.global.insert("!discord".to_string(), ChatCommand::new("The discord is awesome!!"));
+        let style = CommandStyle::default();
+        let mut manage = |badges, message| {
+            let msg = TwitchChatMsg::for_test("neon", badges, "Mod", message);
+            let call = style.parse(&msg.message).unwrap();
+            manage_commands(&msg, &call, &mut commands, &path, &style)
+        };
+
+        assert!(manage("", "!addcom !lurk Enjoy the lurk").is_none());
+        assert_eq!(manage("moderator/1", "!addcom !lurk Enjoy the lurk").unwrap(), "@Mod added !lurk");
+        assert_eq!(
+            manage("moderator/1", "!addcom lurk again").unwrap(),
+            "@Mod !lurk already exists, use !editcom"
+        );
+        assert_eq!(manage("moderator/1", "!addcom !x {nope}").unwrap(), "@Mod !x not saved, unknown variable {nope}");
+        assert_eq!(manage("moderator/1", "!editcom !lurk Bye {user}").unwrap(), "@Mod edited !lurk");
+        assert_eq!(manage("moderator/1", "!editcom !nope hi").unwrap(), "@Mod !nope doesn't exist");
+
+        // Editing a global command overrides it in this channel only
+        assert_eq!(manage("moderator/1", "!EDITCOM !discord Join us").unwrap(), "@Mod edited !discord");
+        assert_eq!(manage("moderator/1", "!delcom !discord").unwrap(), "@Mod deleted !discord");
+        assert_eq!(
+            manage("moderator/1", "!delcom !discord").unwrap(),
+            "@Mod !discord is a global command, it can only be changed in the commands file"
+        );
+
+        assert_eq!(manage("moderator/1", "!renamecom !lurk !bye").unwrap(), "@Mod renamed !lurk to !bye");
+        assert_eq!(manage("moderator/1", "!renamecom !nope !other").unwrap(), "@Mod !nope doesn't exist");
+        assert_eq!(manage("moderator/1", "!renamecom !bye !discord").unwrap(), "@Mod !discord already exists");
+        assert_eq!(manage("moderator/1", "!delcom !nope").unwrap(), "@Mod !nope doesn't exist");
+
+        let saved = crate::hot_reload::load_commands(&path).unwrap();
+        assert_eq!(saved.global["!discord"].response, "The discord is awesome!!");
+        assert_eq!(saved.channels["neon"].len(), 1);
+        assert_eq!(saved.channels["neon"]["!bye"].response, "Bye {user}");
+    }
+
+    #[test]
+    fn case_sensitive_management_commands() {
+        let path = TempPath::new("manage-commands-case.json");
+        let mut commands = CommandSet::default();
+        let style = CommandStyle {
+            case_sensitive: true,
+            ..CommandStyle::default()
+        };
+        let msg = TwitchChatMsg::for_test("neon", "moderator/1", "Mod", "!ADDCOM !lurk Enjoy the lurk");
+        let call = style.parse(&msg.message).unwrap();
+
+        assert!(manage_commands(&msg, &call, &mut commands, &path, &style).is_none());
+        assert!(commands.channels.is_empty());
+    }
+}
@@ -0,0 +1,419 @@
+use config::ConfigError;
+use log::{error, info};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::twitch::chat_bot::command_parser::{CommandCall, CommandStyle};
+use crate::twitch::chat_bot::msg_parser::TwitchChatMsg;
+use crate::twitch::chat_bot::permissions::Permission;
+use crate::twitch::chat_bot::punishments::{Ladder, Offences, Punishment};
+
+/// A link like `https://example.com/path`, `www.example.com`, `discord.gg/abc` or `bigfollows.com`,
+/// capturing the domain
+///
+/// A bare domain needs a common top level domain or a path after it,
+/// so file names like `main.rs` or `serde.json` can be mentioned
+const LINK_PATTERN: &str = concat!(
+    r"(?i)(?:\bhttps?://|\bwww\.)((?:[a-z0-9-]+\.)+[a-z]{2,})\b(?:[/:?#]\S*)?",
+    r"|\b((?:[a-z0-9-]+\.)+(?:com|net|org|gg|tv|io|co|me|ly|xyz|ru|info|biz|link|live|app|shop|site|online|top))\b(?:[/:?#]\S*)?",
+    r"|\b((?:[a-z0-9-]+\.)+[a-z]{2,})/\S*",
+);
+
+/// What a filter does with a message, deleting it unless a timeout is set
+///
+/// Ignored when there's a punishment ladder
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct FilterAction {
+    /// Seconds to time the user out for
+    pub timeout: Option<u64>,
+}
+
+impl FilterAction {
+    #[must_use]
+    pub fn punishment(&self) -> Punishment {
+        self.timeout.map_or(Punishment::Delete, Punishment::Timeout)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LinkFilter {
+    /// Domains that may be posted, subdomains included
+    pub allowed_domains: Vec<String>,
+    /// Seconds a `!permit` lasts
+    pub permit_seconds: u64,
+    /// A permit is used up by the first message with a link
+    pub permit_once: bool,
+    #[serde(flatten)]
+    pub action: FilterAction,
+}
+
+impl Default for LinkFilter {
+    fn default() -> Self {
+        Self {
+            allowed_domains: Vec::new(),
+            permit_seconds: 60,
+            permit_once: true,
+            action: FilterAction::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CapsFilter {
+    /// Shorter messages are never caps spam
+    pub min_length: usize,
+    /// Percentage of letters that may be uppercase
+    pub max_percent: usize,
+    #[serde(flatten)]
+    pub action: FilterAction,
+}
+
+impl Default for CapsFilter {
+    fn default() -> Self {
+        Self {
+            min_length: 15,
+            max_percent: 70,
+            action: FilterAction::default(),
+        }
+    }
+}
+
+/// A filter with a single limit, like the number of emotes in a message
+#[derive(Deserialize, Debug, Clone)]
+pub struct LimitFilter {
+    pub max: usize,
+    #[serde(flatten)]
+    pub action: FilterAction,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct BannedFilter {
+    /// Words and phrases matched as whole words, ignoring case
+    pub words: Vec<String>,
+    pub patterns: Vec<String>,
+    #[serde(flatten)]
+    pub action: FilterAction,
+}
+
+/// The `moderation` setting, a filter is off unless it's configured
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ModerationSettings {
+    /// Chatters with this permission or higher are never filtered
+    pub exempt: Permission,
+    pub links: Option<LinkFilter>,
+    pub caps: Option<CapsFilter>,
+    pub repeated_chars: Option<LimitFilter>,
+    pub emotes: Option<LimitFilter>,
+    pub banned: Option<BannedFilter>,
+    pub length: Option<LimitFilter>,
+    pub ladder: Option<Ladder>,
+}
+
+impl Default for ModerationSettings {
+    fn default() -> Self {
+        Self {
+            exempt: Permission::Vip,
+            links: None,
+            caps: None,
+            repeated_chars: None,
+            emotes: None,
+            banned: None,
+            length: None,
+            ladder: None,
+        }
+    }
+}
+
+/// Why a message was filtered and what the filter does about it
+#[derive(Debug, PartialEq, Eq)]
+pub struct Violation {
+    pub reason: &'static str,
+    pub punishment: Punishment,
+}
+
+/// The moderation filters with their regexes compiled
+#[derive(Debug, Clone)]
+pub struct ModerationFilter {
+    settings: ModerationSettings,
+    link: Regex,
+    banned: Vec<Regex>,
+}
+
+impl Default for ModerationFilter {
+    fn default() -> Self {
+        Self {
+            settings: ModerationSettings::default(),
+            link: Regex::new(LINK_PATTERN).expect("the link pattern is valid"),
+            banned: Vec::new(),
+        }
+    }
+}
+
+impl ModerationFilter {
+    /// # Errors
+    /// Returns an error if a banned pattern isn't a valid regex
+    pub fn new(settings: ModerationSettings) -> Result<Self, ConfigError> {
+        let banned = settings.banned.as_ref().map_or_else(
+            || Ok(Vec::new()),
+            |banned| {
+                let words = banned
+                    .words
+                    .iter()
+                    .map(|word| format!(r"(?i)\b{}\b", regex::escape(word)));
+                words
+                    .chain(banned.patterns.iter().cloned())
+                    .map(|pattern| {
+                        Regex::new(&pattern).map_err(|err| {
+                            ConfigError::Message(format!("moderation.banned: {err}"))
+                        })
+                    })
+                    .collect()
+            },
+        )?;
+
+        Ok(Self {
+            settings,
+            banned,
+            ..Self::default()
+        })
+    }
+
+    /// The link filter, if it's used
+    #[must_use]
+    pub const fn links(&self) -> Option<&LinkFilter> {
+        self.settings.links.as_ref()
+    }
+
+    /// Runs the filters in order, the first one a message breaks decides what happens
+    ///
+    /// A link from a chatter with a `!permit` is let through
+    pub fn check(&self, msg: &TwitchChatMsg, permits: &mut Permits) -> Option<Violation> {
+        if Permission::of(msg) >= self.settings.exempt {
+            return None;
+        }
+        let text = msg.message.trim();
+        let violation = |reason, action: &FilterAction| {
+            Some(Violation {
+                reason,
+                punishment: action.punishment(),
+            })
+        };
+
+        if let Some(banned) = &self.settings.banned {
+            if self.banned.iter().any(|regex| regex.is_match(text)) {
+                return violation("banned phrase", &banned.action);
+            }
+        }
+        if let Some(links) = &self.settings.links {
+            let allowed = |domain: &str| {
+                let domain = domain.to_lowercase();
+                links.allowed_domains.iter().any(|allowed| {
+                    let allowed = allowed.to_lowercase();
+                    domain == allowed || domain.ends_with(&format!(".{allowed}"))
+                })
+            };
+            if self
+                .link
+                .captures_iter(text)
+                .filter_map(|link| link.iter().skip(1).flatten().next())
+                .any(|domain| !allowed(domain.as_str()))
+                && !permits.take(&msg.channel_name, &msg.login, links)
+            {
+                return violation("links aren't allowed", &links.action);
+            }
+        }
+        if let Some(length) = &self.settings.length {
+            if text.chars().count() > length.max {
+                return violation("message too long", &length.action);
+            }
+        }
+        if let Some(caps) = &self.settings.caps {
+            let letters = text.chars().filter(|c| c.is_alphabetic()).count();
+            let upper = text.chars().filter(|c| c.is_uppercase()).count();
+            if letters >= caps.min_length && upper * 100 > letters * caps.max_percent {
+                return violation("too many caps", &caps.action);
+            }
+        }
+        if let Some(repeated) = &self.settings.repeated_chars {
+            if longest_run(text) > repeated.max {
+                return violation("repeated characters", &repeated.action);
+            }
+        }
+        if let Some(emotes) = &self.settings.emotes {
+            if msg.emotes.len() > emotes.max {
+                return violation("too many emotes", &emotes.action);
+            }
+        }
+
+        None
+    }
+
+    #[must_use]
+    pub const fn ladder(&self) -> Option<&Ladder> {
+        self.settings.ladder.as_ref()
+    }
+
+    /// The chat commands that punish a violation,
+    /// climbing the ladder if there is one instead of using the filter's own action
+    pub fn punish(&self, violation: Violation, msg: &TwitchChatMsg, offences: &mut Offences) -> Vec<String> {
+        let punishment = self.ladder().map_or(violation.punishment, |ladder| {
+            let window = Duration::from_secs(ladder.window);
+            let count = offences.record(&msg.channel_name, &msg.login, window);
+            if let Err(err) = offences.save() {
+                error!("Could not save moderation offences: {}", err);
+            }
+            ladder.step(count)
+        });
+
+        info!(
+            "Filtered <{}> in #{} ({}): {:?}",
+            msg.display_name, msg.channel_name, violation.reason, punishment
+        );
+        punishment.commands(msg, violation.reason)
+    }
+}
+
+/// Chatters a moderator allowed to post a link, per channel and login
+#[derive(Debug, Default)]
+pub struct Permits {
+    granted: HashMap<(String, String), Instant>,
+}
+
+impl Permits {
+    pub fn grant(&mut self, channel: &str, login: &str) {
+        self.granted
+            .insert((channel.to_string(), login.to_lowercase()), Instant::now());
+    }
+
+    /// Whether a chatter may post a link now, using up a one-shot permit
+    fn take(&mut self, channel: &str, login: &str, links: &LinkFilter) -> bool {
+        let key = (channel.to_string(), login.to_string());
+        let Some(granted) = self.granted.get(&key) else {
+            return false;
+        };
+        if granted.elapsed() > Duration::from_secs(links.permit_seconds) {
+            self.granted.remove(&key);
+            return false;
+        }
+
+        info!("<{}> used a permit to post a link in #{}", login, channel);
+        if links.permit_once {
+            self.granted.remove(&key);
+        }
+        true
+    }
+}
+
+/// Handles `!permit <user>` from moderators
+///
+/// Returns the reply for chat, or None if the message wasn't a permit
+pub fn permit_command(
+    msg: &TwitchChatMsg,
+    call: &CommandCall<'_>,
+    permits: &mut Permits,
+    style: &CommandStyle,
+    links: Option<&LinkFilter>,
+) -> Option<String> {
+    if !style.matches(&style.trigger("permit"), call.name) {
+        return None;
+    }
+    if Permission::of(msg) < Permission::Moderator {
+        info!("<{}> tried to use {} without permission", msg.display_name, call.name);
+        return None;
+    }
+
+    let user = &msg.display_name;
+    let Some(login) = call.args.first().map(|login| login.trim_start_matches('@').to_lowercase()) else {
+        return Some(format!("@{user} usage: {}permit <user>", style.prefix));
+    };
+    let Some(links) = links else {
+        return Some(format!("@{user} links aren't filtered, there's nothing to permit"));
+    };
+
+    permits.grant(&msg.channel_name, &login);
+    info!(
+        "<{}> permitted {} to post a link in #{} for {}s",
+        user, login, msg.channel_name, links.permit_seconds
+    );
+
+    let how_many = if links.permit_once { "a link" } else { "links" };
+    Some(format!(
+        "@{login} you can post {how_many} in the next {}s",
+        links.permit_seconds
+    ))
+}
+
+/// Length of the longest run of the same character
+fn longest_run(text: &str) -> usize {
+    let mut longest = 0;
+    let mut run = 0;
+    let mut last = None;
+
+    for c in text.chars() {
+        run = if last == Some(c) { run + 1 } else { 1 };
+        last = Some(c);
+        longest = longest.max(run);
+    }
+
+    longest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_links_caps_and_banned_words() {
+        let settings: ModerationSettings = serde_json::from_str(
+            r#"{
+                "links": { "allowed_domains": ["github.com"], "timeout": 10 },
+                "caps": {},
+                "banned": { "words": ["bad word"], "timeout": 600 }
+            }"#,
+        )
+        .unwrap();
+        let filter = ModerationFilter::new(settings).unwrap();
+        let permits = &mut Permits::default();
+        let chat = |message| TwitchChatMsg::for_test("neonraytracer", "", "Bob", message);
+
+        let link = chat("check out https://spam.example.com/free");
+        let violation = filter.check(&link, permits).unwrap();
+        assert_eq!(violation.punishment.commands(&link, violation.reason), vec!["/timeout bob 10 links aren't allowed"]);
+        assert!(filter.check(&chat("code is on https://gist.github.com/neon"), permits).is_none());
+        assert!(filter.check(&chat("buy followers at www.spam.example"), permits).is_some());
+        // File and crate names aren't links
+        assert!(filter.check(&chat("check main.rs and Cargo.toml"), permits).is_none());
+        assert!(filter.check(&chat("use serde.json"), permits).is_none());
+        // Spam usually comes without a scheme
+        assert!(filter.check(&chat("best viewers at bigfollows.com/x"), permits).is_some());
+        assert!(filter.check(&chat("join discord.gg/abc"), permits).is_some());
+        assert!(filter.check(&chat("cheap-views.xyz"), permits).is_some());
+        assert!(filter.check(&chat("see spam.example/free"), permits).is_some());
+        assert!(filter.check(&chat("my code: gist.github.com/neon"), permits).is_none());
+
+        let caps = chat("WHY IS THIS NOT WORKING");
+        assert_eq!(filter.check(&caps, permits).unwrap().punishment.commands(&caps, ""), vec!["/delete abc-123"]);
+        assert_eq!(filter.check(&chat("that's a BAD WORD"), permits).unwrap().reason, "banned phrase");
+        assert!(filter.check(&chat("Hello there, how is it going?"), permits).is_none());
+
+        let vip = TwitchChatMsg::for_test("neonraytracer", "vip/1", "Bob", "https://spam.example.com");
+        assert!(filter.check(&vip, permits).is_none());
+
+        permits.grant("neonraytracer", "Bob");
+        assert!(filter.check(&link, permits).is_none());
+        assert!(filter.check(&link, permits).is_some());
+    }
+
+    #[test]
+    fn counts_repeated_characters() {
+        assert_eq!(longest_run("heyyyyy!!"), 5);
+        assert_eq!(longest_run(""), 0);
+    }
+}
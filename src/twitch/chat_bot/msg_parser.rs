@@ -1,49 +1,488 @@
 /* Courtesy of Togglebit, the parser master! */
+use chrono::{DateTime, TimeZone, Utc};
+use std::collections::HashMap;
 
+/// A single `IRCv3` line, as sent by Twitch chat
+/// `[@tags] [:prefix] <command> [params] [:trailing]`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct IrcMessage {
+    pub tags: HashMap<String, String>,
+    pub prefix: Option<String>,
+    pub command: String,
+    pub params: Vec<String>,
+}
+
+impl IrcMessage {
+    /// Parses one line, returns None if the line has no command
+    #[must_use]
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut rest = line.trim_end_matches(&['\r', '\n'][..]);
+        let mut tags = HashMap::new();
+
+        if let Some(stripped) = rest.strip_prefix('@') {
+            let (raw_tags, remaining) = split_word(stripped);
+            for tag in raw_tags.split(';').filter(|tag| !tag.is_empty()) {
+                let mut key_value = tag.splitn(2, '=');
+                let key = key_value.next().unwrap_or_default();
+                let value = key_value.next().unwrap_or_default();
+                tags.insert(key.to_string(), unescape_tag_value(value));
+            }
+            rest = remaining;
+        }
+
+        let mut prefix = None;
+        if let Some(stripped) = rest.strip_prefix(':') {
+            let (raw_prefix, remaining) = split_word(stripped);
+            prefix = Some(raw_prefix.to_string());
+            rest = remaining;
+        }
+
+        let (command, mut rest) = split_word(rest);
+        if command.is_empty() {
+            return None;
+        }
+
+        let mut params = Vec::new();
+        while !rest.is_empty() {
+            if let Some(trailing) = rest.strip_prefix(':') {
+                params.push(trailing.to_string());
+                break;
+            }
+            let (param, remaining) = split_word(rest);
+            params.push(param.to_string());
+            rest = remaining;
+        }
+
+        Some(Self {
+            tags,
+            prefix,
+            command: command.to_uppercase(),
+            params,
+        })
+    }
+
+    /// Value of a tag, empty tags are treated as missing
+    #[must_use]
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags
+            .get(key)
+            .map(String::as_str)
+            .filter(|value| !value.is_empty())
+    }
+
+    /// The nick part of a `nick!user@host` prefix
+    #[must_use]
+    pub fn nick(&self) -> Option<&str> {
+        self.prefix
+            .as_deref()
+            .map(|prefix| prefix.split('!').next().unwrap_or(prefix))
+    }
+
+    /// First param without the leading `#`, i.e. the channel login
+    #[must_use]
+    pub fn channel(&self) -> Option<&str> {
+        self.params
+            .first()
+            .map(|channel| channel.trim_start_matches('#'))
+    }
+
+    /// The trailing (last) param
+    #[must_use]
+    pub fn trailing(&self) -> Option<&str> {
+        self.params.last().map(String::as_str)
+    }
+}
+
+/// An emote in a chat message, `start` and `end` are inclusive character positions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Emote {
+    pub id: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Emote {
+    /// The emote's text in the message, like `Kappa`
+    #[must_use]
+    pub fn text<'a>(&self, message: &'a str) -> Option<&'a str> {
+        let mut positions = message
+            .char_indices()
+            .map(|(idx, _)| idx)
+            .chain(Some(message.len()));
+        let start = positions.nth(self.start)?;
+        let end = positions.nth(self.end - self.start)?;
+
+        message.get(start..end)
+    }
+}
+
+/// The message a reply was sent to, from the `reply-parent-*` tags
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ReplyParent {
+    pub msg_id: String,
+    pub user_id: String,
+    pub login: String,
+    pub display_name: String,
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct TwitchChatMsg {
+    /// Unique id of the message, used to delete or reply to it
+    pub id: String,
+    pub user_id: String,
     pub display_name: String,
+    pub login: String,
     pub channel_name: String,
     pub message: String,
+    /// Name color like `#1E90FF`, None if the user never picked one
+    pub color: Option<String>,
+    /// Badge name to badge version, e.g. `subscriber` => `12`
+    pub badges: HashMap<String, String>,
+    pub emotes: Vec<Emote>,
+    /// Bits cheered with the message
+    pub bits: u64,
+    pub is_mod: bool,
+    pub is_subscriber: bool,
+    /// The first message the user ever sent in the channel
+    pub first_msg: bool,
+    /// The user chats every now and then, but hasn't in a while
+    pub returning_chatter: bool,
+    pub reply_parent: Option<ReplyParent>,
+    /// When the server received the message
+    pub sent_at: Option<DateTime<Utc>>,
+    pub tags: HashMap<String, String>,
 }
 
-// Takes pre-parsed WS Text::Message from twitch chat
-pub fn parse_twitch_msg(chat_msg: String) -> Option<TwitchChatMsg> {
-    if chat_msg.contains("PRIVMSG") {
-        // Parse tags
-        let tag_idx = chat_msg.to_string().find(" :").unwrap();
-        let tag_arr = chat_msg.to_owned().drain(..tag_idx + 1).collect::<String>();
-
-        // Create key pair values for each tag
-        let tag_values = tag_arr
-            .split(";")
-            .map(|tags| tags.split("=").collect::<Vec<_>>());
-
-        // Parse message
-        let message = chat_msg.splitn(3, " :").last();
-
-        // Parse channel name
-        let priv_msg = &chat_msg.split("PRIVMSG").collect::<Vec<&str>>()[1].to_string();
-        let channel_name = priv_msg.split(" :").collect::<Vec<&str>>()[0];
-
-        // Parse user name
-        let display_name = tag_values
-            .filter_map(|tag| {
-                if tag[0].contains("display-name") {
-                    Some(tag[1])
-                } else {
-                    None
-                }
-            })
-            .collect::<String>();
+/// NOTICEs from the server, typed by their `msg-id` where the bot has to act on them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChatNotice {
+    /// The token was wrong or expired, the server closes the connection after this
+    LoginFailed(String),
+    /// A message was dropped because the bot sent too many
+    RateLimited { channel: String },
+    /// The bot is banned from the channel
+    Banned { channel: String },
+    Other {
+        channel: String,
+        msg_id: String,
+        message: String,
+    },
+}
 
-        // println!("{}", format!("User {} \nWrote: {}\nIn the {} channel", display_name, message.unwrap(), channel_name)); // For debugging
+impl ChatNotice {
+    #[must_use]
+    pub fn from_irc(irc: &IrcMessage) -> Self {
+        let channel = irc.channel().unwrap_or("*").to_string();
+        let message = irc.trailing().unwrap_or_default().to_string();
 
-        return Some(TwitchChatMsg {
-            display_name,
-            channel_name: channel_name.to_string(),
-            message: message.unwrap().to_string(),
+        match irc.tag("msg-id") {
+            Some("msg_ratelimit") => Self::RateLimited { channel },
+            Some("msg_banned") => Self::Banned { channel },
+            // Login failures come before tags are enabled, so they only have the text
+            None if message.starts_with("Login authentication failed")
+                || message.starts_with("Improperly formatted auth") =>
+            {
+                Self::LoginFailed(message)
+            }
+            msg_id => Self::Other {
+                channel,
+                msg_id: msg_id.unwrap_or_default().to_string(),
+                message,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for ChatNotice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LoginFailed(message) => write!(f, "Twitch chat login failed: {message}"),
+            Self::RateLimited { channel } => write!(f, "Sending too fast, a message to #{channel} was dropped"),
+            Self::Banned { channel } => write!(f, "The bot is banned in #{channel}"),
+            Self::Other {
+                channel,
+                msg_id,
+                message,
+            } => write!(f, "#{channel} {msg_id}: {message}"),
+        }
+    }
+}
+
+/// Everything Twitch chat sends us, typed by IRC command
+#[derive(Debug)]
+pub enum TwitchMessage {
+    Privmsg(Box<TwitchChatMsg>),
+    UserNotice(IrcMessage),
+    ClearChat(IrcMessage),
+    ClearMsg(IrcMessage),
+    RoomState(IrcMessage),
+    UserState(IrcMessage),
+    Notice(ChatNotice),
+    Join { channel: String, login: String },
+    Part { channel: String, login: String },
+    Ping(String),
+    Reconnect,
+    Other(IrcMessage),
+}
+
+impl TwitchChatMsg {
+    fn from_irc(irc: IrcMessage) -> Option<Self> {
+        let login = irc.nick()?.to_string();
+        let channel_name = irc.channel()?.to_string();
+        let message = if irc.params.len() > 1 {
+            irc.trailing().unwrap_or_default().to_string()
+        } else {
+            String::new()
+        };
+        let display_name = irc.tag("display-name").unwrap_or(&login).to_string();
+        let badges = parse_badges(irc.tag("badges").unwrap_or_default());
+        let flag = |key| irc.tag(key) == Some("1");
+        let owned = |key| irc.tag(key).map(ToString::to_string);
+
+        let reply_parent = owned("reply-parent-msg-id").map(|msg_id| ReplyParent {
+            msg_id,
+            user_id: owned("reply-parent-user-id").unwrap_or_default(),
+            login: owned("reply-parent-user-login").unwrap_or_default(),
+            display_name: owned("reply-parent-display-name").unwrap_or_default(),
+            message: owned("reply-parent-msg-body").unwrap_or_default(),
         });
-    } else {
-        return None;
+        let sent_at = irc
+            .tag("tmi-sent-ts")
+            .and_then(|ts| ts.parse().ok())
+            .map(|ts| Utc.timestamp_millis(ts));
+
+        Some(Self {
+            id: owned("id").unwrap_or_default(),
+            user_id: owned("user-id").unwrap_or_default(),
+            display_name,
+            login,
+            channel_name,
+            message,
+            color: owned("color"),
+            badges,
+            emotes: parse_emotes(irc.tag("emotes").unwrap_or_default()),
+            bits: irc.tag("bits").and_then(|bits| bits.parse().ok()).unwrap_or_default(),
+            is_mod: flag("mod"),
+            is_subscriber: flag("subscriber"),
+            first_msg: flag("first-msg"),
+            returning_chatter: flag("returning-chatter"),
+            reply_parent,
+            sent_at,
+            tags: irc.tags,
+        })
+    }
+
+    /// A message for tests, `badges` like in the tag, e.g. `moderator/1,subscriber/12`
+    #[cfg(test)]
+    #[must_use]
+    pub fn for_test(channel: &str, badges: &str, login: &str, message: &str) -> Self {
+        Self {
+            id: "abc-123".to_string(),
+            display_name: login.to_string(),
+            login: login.to_lowercase(),
+            channel_name: channel.to_string(),
+            message: message.to_string(),
+            badges: parse_badges(badges),
+            ..Self::default()
+        }
+    }
+}
+
+// Takes pre-parsed WS Text::Message from twitch chat, one line at a time
+#[must_use]
+pub fn parse_twitch_msg(chat_msg: &str) -> Option<TwitchMessage> {
+    let irc = IrcMessage::parse(chat_msg)?;
+
+    let membership = |irc: &IrcMessage| {
+        Some((irc.channel()?.to_string(), irc.nick()?.to_string()))
+    };
+
+    let msg = match irc.command.as_str() {
+        "PRIVMSG" => TwitchMessage::Privmsg(Box::new(TwitchChatMsg::from_irc(irc)?)),
+        "USERNOTICE" => TwitchMessage::UserNotice(irc),
+        "CLEARCHAT" => TwitchMessage::ClearChat(irc),
+        "CLEARMSG" => TwitchMessage::ClearMsg(irc),
+        "ROOMSTATE" => TwitchMessage::RoomState(irc),
+        "USERSTATE" => TwitchMessage::UserState(irc),
+        "NOTICE" => TwitchMessage::Notice(ChatNotice::from_irc(&irc)),
+        "JOIN" => {
+            let (channel, login) = membership(&irc)?;
+            TwitchMessage::Join { channel, login }
+        }
+        "PART" => {
+            let (channel, login) = membership(&irc)?;
+            TwitchMessage::Part { channel, login }
+        }
+        "PING" => TwitchMessage::Ping(irc.trailing().unwrap_or("tmi.twitch.tv").to_string()),
+        "RECONNECT" => TwitchMessage::Reconnect,
+        _ => TwitchMessage::Other(irc),
+    };
+
+    Some(msg)
+}
+
+/// Parses `name/version` pairs from the `badges` tag
+fn parse_badges(badges: &str) -> HashMap<String, String> {
+    badges
+        .split(',')
+        .filter(|badge| !badge.is_empty())
+        .map(|badge| {
+            let mut name_version = badge.splitn(2, '/');
+            let name = name_version.next().unwrap_or_default().to_string();
+            let version = name_version.next().unwrap_or_default().to_string();
+            (name, version)
+        })
+        .collect()
+}
+
+/// Parses emote positions from the `emotes` tag, like `25:0-4,12-16/1902:6-10`,
+/// sorted by where they are in the message
+fn parse_emotes(emotes: &str) -> Vec<Emote> {
+    let mut parsed: Vec<Emote> = emotes
+        .split('/')
+        .filter_map(|emote| emote.split_once(':'))
+        .flat_map(|(id, positions)| {
+            positions.split(',').filter_map(move |position| {
+                let (start, end) = position.split_once('-')?;
+                Some(Emote {
+                    id: id.to_string(),
+                    start: start.parse().ok()?,
+                    end: end.parse().ok()?,
+                })
+            })
+        })
+        .filter(|emote| emote.start <= emote.end)
+        .collect();
+    parsed.sort_by_key(|emote| emote.start);
+
+    parsed
+}
+
+/// Splits off the first space separated word
+fn split_word(input: &str) -> (&str, &str) {
+    input.find(' ').map_or((input, ""), |idx| {
+        (&input[..idx], input[idx + 1..].trim_start_matches(' '))
+    })
+}
+
+/// Reverses the `IRCv3` tag value escaping
+/// <https://ircv3.net/specs/extensions/message-tags#escaping-values>
+fn unescape_tag_value(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(':') => unescaped.push(';'),
+            Some('s') => unescaped.push(' '),
+            Some('r') => unescaped.push('\r'),
+            Some('n') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            // A trailing lone backslash is dropped
+            None => {}
+        }
+    }
+
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn privmsg() {
+        let line = "@badge-info=;badges=broadcaster/1;display-name=NeonRaytracer;emotes=;mod=0 :neonraytracer!neonraytracer@neonraytracer.tmi.twitch.tv PRIVMSG #neonraytracer :!discord please :)\r\n";
+
+        match parse_twitch_msg(line) {
+            Some(TwitchMessage::Privmsg(msg)) => {
+                assert_eq!(msg.display_name, "NeonRaytracer");
+                assert_eq!(msg.login, "neonraytracer");
+                assert_eq!(msg.channel_name, "neonraytracer");
+                assert_eq!(msg.message, "!discord please :)");
+                assert_eq!(msg.badges.get("broadcaster").unwrap(), "1");
+                assert!(!msg.is_mod);
+            }
+            other => panic!("Expected PRIVMSG, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn privmsg_metadata() {
+        let line = "@badges=subscriber/12;bits=100;color=#1E90FF;display-name=Bob;emotes=25:0-4,18-22/1902:6-10;first-msg=1;id=b34ccfc7-4977-403a-8a94-33c6bac34fb8;reply-parent-msg-id=abc;reply-parent-user-login=neonraytracer;reply-parent-display-name=NeonRaytracer;reply-parent-msg-body=hello\\sthere;returning-chatter=0;tmi-sent-ts=1604600000000;user-id=1337 :bob!bob@bob.tmi.twitch.tv PRIVMSG #neonraytracer :Kappa Keepo cheer Kappa";
+
+        let Some(TwitchMessage::Privmsg(msg)) = parse_twitch_msg(line) else {
+            panic!("Expected PRIVMSG");
+        };
+        assert_eq!(msg.id, "b34ccfc7-4977-403a-8a94-33c6bac34fb8");
+        assert_eq!(msg.user_id, "1337");
+        assert_eq!(msg.color.as_deref(), Some("#1E90FF"));
+        assert_eq!(msg.bits, 100);
+        assert!(msg.first_msg && !msg.returning_chatter);
+        let emotes: Vec<_> = msg.emotes.iter().filter_map(|emote| emote.text(&msg.message)).collect();
+        assert_eq!(emotes, vec!["Kappa", "Keepo", "Kappa"]);
+        let parent = msg.reply_parent.unwrap();
+        assert_eq!((parent.login.as_str(), parent.message.as_str()), ("neonraytracer", "hello there"));
+        assert_eq!(msg.sent_at.unwrap().timestamp(), 1_604_600_000);
+    }
+
+    #[test]
+    fn tag_unescaping() {
+        let irc = IrcMessage::parse(r"@system-msg=5\sraiders\:\sfrom\\there;empty= :tmi.twitch.tv USERNOTICE #chan")
+            .unwrap();
+
+        assert_eq!(irc.tags.get("system-msg").unwrap(), r"5 raiders; from\there");
+        assert_eq!(irc.tag("empty"), None);
+        assert_eq!(irc.command, "USERNOTICE");
+        assert_eq!(irc.channel(), Some("chan"));
+    }
+
+    #[test]
+    fn commands_without_tags() {
+        assert!(matches!(
+            parse_twitch_msg("PING :tmi.twitch.tv"),
+            Some(TwitchMessage::Ping(server)) if server == "tmi.twitch.tv"
+        ));
+        assert!(matches!(
+            parse_twitch_msg(":tmi.twitch.tv RECONNECT"),
+            Some(TwitchMessage::Reconnect)
+        ));
+        assert!(matches!(
+            parse_twitch_msg(":bob!bob@bob.tmi.twitch.tv JOIN #neonraytracer"),
+            Some(TwitchMessage::Join { channel, login }) if channel == "neonraytracer" && login == "bob"
+        ));
+        assert!(parse_twitch_msg("").is_none());
+    }
+
+    #[test]
+    fn notices() {
+        let notice = |line| match parse_twitch_msg(line) {
+            Some(TwitchMessage::Notice(notice)) => notice,
+            other => panic!("Expected NOTICE, got {:?}", other),
+        };
+
+        assert_eq!(
+            notice(":tmi.twitch.tv NOTICE * :Login authentication failed"),
+            ChatNotice::LoginFailed("Login authentication failed".to_string())
+        );
+        assert_eq!(
+            notice("@msg-id=msg_ratelimit :tmi.twitch.tv NOTICE #neonraytracer :Your message was not sent because you are sending messages too quickly."),
+            ChatNotice::RateLimited { channel: "neonraytracer".to_string() }
+        );
+        assert!(matches!(
+            notice("@msg-id=emote_only_on :tmi.twitch.tv NOTICE #neonraytracer :This room is in emote-only mode."),
+            ChatNotice::Other { msg_id, .. } if msg_id == "emote_only_on"
+        ));
+        // A chat message that says PING is just a chat message
+        assert!(matches!(
+            parse_twitch_msg(":bob!bob@bob.tmi.twitch.tv PRIVMSG #neonraytracer :PING"),
+            Some(TwitchMessage::Privmsg(_))
+        ));
     }
 }
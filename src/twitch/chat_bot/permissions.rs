@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+
+use crate::twitch::chat_bot::msg_parser::TwitchChatMsg;
+
+/// Who may use a command, each level includes everyone above it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    #[default]
+    Everyone,
+    Subscriber,
+    Vip,
+    Moderator,
+    Broadcaster,
+}
+
+impl Permission {
+    /// Highest permission level a chatter has, derived from their badges
+    #[must_use]
+    pub fn of(msg: &TwitchChatMsg) -> Self {
+        if msg.badges.contains_key("broadcaster") || msg.login == msg.channel_name {
+            Self::Broadcaster
+        } else if msg.is_mod || msg.badges.contains_key("moderator") {
+            Self::Moderator
+        } else if msg.badges.contains_key("vip") {
+            Self::Vip
+        } else if is_subscriber(msg) {
+            Self::Subscriber
+        } else {
+            Self::Everyone
+        }
+    }
+}
+
+/// Subscribed to the channel, founders lose the `subscriber` badge but are still subscribed
+#[must_use]
+pub fn is_subscriber(msg: &TwitchChatMsg) -> bool {
+    msg.is_subscriber || msg.badges.contains_key("subscriber") || msg.badges.contains_key("founder")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permission_from_badges_and_tags() {
+        let of = |badges, login| Permission::of(&TwitchChatMsg::for_test("neon", badges, login, "hi"));
+
+        assert_eq!(of("broadcaster/1,subscriber/0", "neon"), Permission::Broadcaster);
+        // Without a badge the channel owner is still the broadcaster
+        assert_eq!(of("", "Neon"), Permission::Broadcaster);
+        assert_eq!(of("moderator/1,vip/1,subscriber/12", "bob"), Permission::Moderator);
+        assert_eq!(of("vip/1,subscriber/12", "bob"), Permission::Vip);
+        assert_eq!(of("subscriber/12", "bob"), Permission::Subscriber);
+        assert_eq!(of("founder/0", "bob"), Permission::Subscriber);
+        assert_eq!(of("premium/1", "bob"), Permission::Everyone);
+        assert_eq!(of("", "bob"), Permission::Everyone);
+
+        let tagged = |is_mod, is_subscriber| {
+            Permission::of(&TwitchChatMsg {
+                is_mod,
+                is_subscriber,
+                ..TwitchChatMsg::for_test("neon", "", "bob", "hi")
+            })
+        };
+        assert_eq!(tagged(true, true), Permission::Moderator);
+        assert_eq!(tagged(false, true), Permission::Subscriber);
+    }
+}
@@ -0,0 +1,324 @@
+use log::{error, info};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::twitch::chat_bot::command_parser::{CommandCall, CommandStyle};
+use crate::twitch::chat_bot::json_file::{load_or_default, save_atomic};
+use crate::twitch::chat_bot::msg_parser::TwitchChatMsg;
+use crate::twitch::chat_bot::permissions::Permission;
+use crate::twitch::stream_status::StreamStatus;
+
+/// Chatters `!leaderboard` shows
+const LEADERBOARD_SIZE: usize = 5;
+/// Points aren't paid more often than this
+const MIN_INTERVAL: u64 = 60;
+
+/// How viewers earn points, the `points` setting
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct PointsSettings {
+    /// What the points are called in chat
+    pub name: String,
+    /// Seconds between payouts
+    pub interval: u64,
+    /// Paid to everyone in chat while the stream is live
+    pub present: u64,
+    /// Paid on top to everyone who chatted since the last payout
+    pub active: u64,
+    /// Logins that never earn points, like other bots, the bot itself never does
+    pub ignored: Vec<String>,
+}
+
+impl PointsSettings {
+    /// Reads the `points` setting, the defaults are used if it's missing
+    /// # Errors
+    /// Returns an error if the setting has the wrong type or the interval is too short
+    pub fn from_config(settings: &config::Config) -> Result<Self, config::ConfigError> {
+        let points = match settings.get::<Self>("points") {
+            Err(config::ConfigError::NotFound(_)) => Self::default(),
+            points => points?,
+        };
+        if points.interval < MIN_INTERVAL {
+            return Err(config::ConfigError::Message(format!(
+                "points: interval must be at least {MIN_INTERVAL} seconds"
+            )));
+        }
+
+        Ok(points)
+    }
+}
+
+impl Default for PointsSettings {
+    fn default() -> Self {
+        Self {
+            name: "points".to_string(),
+            interval: 5 * 60,
+            present: 5,
+            active: 5,
+            ignored: Vec::new(),
+        }
+    }
+}
+
+/// Points of every viewer per channel, saved to the points file after every change,
+/// along with who's in chat to pay them
+#[derive(Debug, Default)]
+pub struct Points {
+    path: PathBuf,
+    balances: BTreeMap<String, BTreeMap<String, u64>>,
+    present: HashMap<String, HashSet<String>>,
+    active: HashMap<String, HashSet<String>>,
+    last_payout: Option<Instant>,
+}
+
+impl Points {
+    /// Reads the points file
+    /// # Errors
+    /// Returns an error if the file exists but can't be read or parsed
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let balances = load_or_default(&path)?;
+
+        Ok(Self {
+            path,
+            balances,
+            ..Self::default()
+        })
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Someone joined chat, from a JOIN
+    pub fn join(&mut self, channel: &str, login: &str) {
+        self.present
+            .entry(channel.to_string())
+            .or_default()
+            .insert(login.to_lowercase());
+    }
+
+    /// Someone left chat, from a PART
+    pub fn part(&mut self, channel: &str, login: &str) {
+        if let Some(present) = self.present.get_mut(channel) {
+            present.remove(&login.to_lowercase());
+        }
+    }
+
+    /// Someone chatted, they're in chat even if Twitch hasn't sent their JOIN yet
+    pub fn chatted(&mut self, channel: &str, login: &str) {
+        self.join(channel, login);
+        self.active
+            .entry(channel.to_string())
+            .or_default()
+            .insert(login.to_lowercase());
+    }
+
+    /// Points of a viewer, 0 if they have none yet
+    #[must_use]
+    pub fn get(&self, channel: &str, login: &str) -> u64 {
+        self.balances
+            .get(channel)
+            .and_then(|balances| balances.get(&login.to_lowercase()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// The viewers with the most points, most first
+    #[must_use]
+    pub fn top(&self, channel: &str, count: usize) -> Vec<(&str, u64)> {
+        let mut top: Vec<_> = self
+            .balances
+            .get(channel)
+            .into_iter()
+            .flatten()
+            .map(|(login, points)| (login.as_str(), *points))
+            .filter(|(_, points)| *points > 0)
+            .collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        top.truncate(count);
+
+        top
+    }
+
+    /// Adds (or with a negative amount removes) points, a viewer can't go below 0,
+    /// saves the points file and returns the new balance
+    /// # Errors
+    /// Returns an error if the points file couldn't be written, the change is kept anyway
+    pub fn add(&mut self, channel: &str, login: &str, amount: i64) -> io::Result<u64> {
+        let balance = self.balance(channel, login);
+        *balance = if amount < 0 {
+            balance.saturating_sub(amount.unsigned_abs())
+        } else {
+            balance.saturating_add(amount.unsigned_abs())
+        };
+        let balance = *balance;
+
+        self.save().map(|()| balance)
+    }
+
+    /// Pays everyone in the chat of live channels once every `interval`,
+    /// and saves the points file if anyone was paid
+    /// # Errors
+    /// Returns an error if the points file couldn't be written
+    pub fn pay(&mut self, stream_status: &StreamStatus, settings: &PointsSettings, bot: &str) -> io::Result<()> {
+        let interval = Duration::from_secs(settings.interval);
+        let last_payout = *self.last_payout.get_or_insert_with(Instant::now);
+        if last_payout.elapsed() < interval {
+            return Ok(());
+        }
+        self.last_payout = Some(Instant::now());
+
+        // Chatting only counts until the next payout, even if nothing was paid
+        let active = std::mem::take(&mut self.active);
+        let ignored = |login: &str| {
+            login.eq_ignore_ascii_case(bot)
+                || settings
+                    .ignored
+                    .iter()
+                    .any(|ignored| ignored.eq_ignore_ascii_case(login))
+        };
+
+        let present: Vec<_> = self
+            .present
+            .iter()
+            .filter(|(channel, _)| stream_status.is_live(channel))
+            .flat_map(|(channel, logins)| logins.iter().map(move |login| (channel.clone(), login.clone())))
+            .filter(|(_, login)| !ignored(login))
+            .collect();
+        if present.is_empty() {
+            return Ok(());
+        }
+
+        info!("Paying {} {} to {} viewers", settings.present, settings.name, present.len());
+        for (channel, login) in present {
+            let chatted = active.get(&channel).is_some_and(|active| active.contains(&login));
+            let amount = settings.present + if chatted { settings.active } else { 0 };
+            let balance = self.balance(&channel, &login);
+            *balance = balance.saturating_add(amount);
+        }
+
+        self.save()
+    }
+
+    fn balance(&mut self, channel: &str, login: &str) -> &mut u64 {
+        self.balances
+            .entry(channel.to_string())
+            .or_default()
+            .entry(login.to_lowercase())
+            .or_default()
+    }
+
+    fn save(&self) -> io::Result<()> {
+        save_atomic(&self.path, &self.balances)
+    }
+}
+
+/// Handles `!points [user]`, `!leaderboard`, `!addpoints <user> <amount>` and `!removepoints <user> <amount>`
+///
+/// Anyone can see points, adding and removing them is for moderators
+/// Returns the reply for chat, or None if the message wasn't a points command
+pub fn points_command(
+    msg: &TwitchChatMsg,
+    call: &CommandCall<'_>,
+    points: &mut Points,
+    style: &CommandStyle,
+    settings: &PointsSettings,
+) -> Option<String> {
+    let action = ["points", "leaderboard", "addpoints", "removepoints"]
+        .iter()
+        .copied()
+        .find(|action| style.matches(&style.trigger(action), call.name))?;
+
+    let user = &msg.display_name;
+    let channel = &msg.channel_name;
+    let name = &settings.name;
+    let login = call.args.first().map(|login| login.trim_start_matches('@').to_lowercase());
+
+    let reply = match action {
+        "points" => login.map_or_else(
+            || format!("@{user} you have {} {name}", points.get(channel, &msg.login)),
+            |login| format!("{login} has {} {name}", points.get(channel, &login)),
+        ),
+        "leaderboard" => {
+            let top: Vec<_> = points
+                .top(channel, LEADERBOARD_SIZE)
+                .iter()
+                .enumerate()
+                .map(|(place, (login, points))| format!("{}. {login} ({points})", place + 1))
+                .collect();
+            if top.is_empty() {
+                format!("Nobody has any {name} yet")
+            } else {
+                format!("Most {name}: {}", top.join(", "))
+            }
+        }
+        _ if Permission::of(msg) < Permission::Moderator => {
+            info!("<{}> tried to use {} without permission", user, call.name);
+            return None;
+        }
+        _ => {
+            let amount = call.args.get(1).and_then(|amount| amount.parse::<i64>().ok());
+            let (Some(login), Some(amount)) = (login, amount.filter(|amount| *amount > 0)) else {
+                return Some(format!("@{user} usage: {}{action} <user> <amount>", style.prefix));
+            };
+            let amount = if action == "removepoints" { -amount } else { amount };
+
+            info!("<{}> in #{}: {} {} for {}", user, channel, amount, name, login);
+            match points.add(channel, &login, amount) {
+                Ok(balance) => format!("@{user} {login} now has {balance} {name}"),
+                Err(err) => {
+                    error!("Could not save points to {}: {}", points.path().display(), err);
+                    format!("@{user} {login} now has {} {name}, but it couldn't be saved", points.get(channel, &login))
+                }
+            }
+        }
+    };
+
+    Some(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::twitch::chat_bot::json_file::TempPath;
+
+    #[test]
+    fn pays_present_and_active_viewers_while_live() {
+        let path = TempPath::new("points.json");
+        let mut points = Points::load(path.to_path_buf()).unwrap();
+        let settings = PointsSettings {
+            interval: 0,
+            ignored: vec!["OtherBot".to_string()],
+            ..PointsSettings::default()
+        };
+        let mut stream_status = StreamStatus::default();
+
+        points.join("neon", "alice");
+        points.join("neon", "bot");
+        points.join("neon", "otherbot");
+        points.chatted("neon", "Bob");
+        points.pay(&stream_status, &settings, "bot").unwrap();
+        assert_eq!(points.get("neon", "bob"), 0);
+
+        stream_status.went_live("neon", std::time::SystemTime::now());
+        points.chatted("neon", "Bob");
+        points.pay(&stream_status, &settings, "bot").unwrap();
+        assert_eq!(points.get("neon", "alice"), 5);
+        assert_eq!(points.get("neon", "bob"), 10);
+        assert_eq!(points.get("neon", "bot"), 0);
+        assert_eq!(points.get("neon", "otherbot"), 0);
+
+        // Chatting only counts once, leaving stops the payouts
+        points.part("neon", "alice");
+        points.pay(&stream_status, &settings, "bot").unwrap();
+        assert_eq!(points.top("neon", 5), [("bob", 15), ("alice", 5)]);
+
+        assert_eq!(points.add("neon", "alice", -100).unwrap(), 0);
+        let reloaded = Points::load(path.to_path_buf()).unwrap();
+        assert_eq!(reloaded.balances, points.balances);
+    }
+}
@@ -0,0 +1,290 @@
+use log::info;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::twitch::chat_bot::command_parser::{CommandCall, CommandStyle};
+use crate::twitch::chat_bot::msg_parser::TwitchChatMsg;
+use crate::twitch::chat_bot::permissions::Permission;
+
+/// Options a poll can have
+const MIN_OPTIONS: usize = 2;
+const MAX_OPTIONS: usize = 10;
+/// Polls can't run longer than a day
+const MAX_DURATION: u64 = 24 * 60 * 60;
+
+/// How long polls run unless a duration is given, the `poll` setting
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct PollSettings {
+    /// Seconds
+    pub duration: u64,
+}
+
+impl PollSettings {
+    /// Reads the `poll` setting, the defaults are used if it's missing
+    /// # Errors
+    /// Returns an error if the setting has the wrong type or the duration is longer than a day
+    pub fn from_config(settings: &config::Config) -> Result<Self, config::ConfigError> {
+        let poll = match settings.get::<Self>("poll") {
+            Err(config::ConfigError::NotFound(_)) => Self::default(),
+            poll => poll?,
+        };
+        if poll.duration > MAX_DURATION {
+            return Err(config::ConfigError::Message(format!(
+                "poll: duration can't be more than {MAX_DURATION} seconds"
+            )));
+        }
+
+        Ok(poll)
+    }
+}
+
+impl Default for PollSettings {
+    fn default() -> Self {
+        Self { duration: 2 * 60 }
+    }
+}
+
+/// A vote in a channel, viewers vote by sending an option's number or text
+#[derive(Debug)]
+struct Poll {
+    question: String,
+    options: Vec<String>,
+    /// The option every viewer voted for, by login
+    votes: HashMap<String, usize>,
+    ends: Instant,
+}
+
+impl Poll {
+    /// The option a message votes for, if any
+    fn option(&self, message: &str) -> Option<usize> {
+        let message = message.trim();
+        message
+            .parse::<usize>()
+            .ok()
+            .and_then(|number| number.checked_sub(1))
+            .filter(|idx| *idx < self.options.len())
+            .or_else(|| {
+                self.options
+                    .iter()
+                    .position(|option| option.eq_ignore_ascii_case(message))
+            })
+    }
+
+    fn tally(&self) -> Vec<usize> {
+        let mut tally = vec![0; self.options.len()];
+        for idx in self.votes.values() {
+            tally[*idx] += 1;
+        }
+
+        tally
+    }
+
+    /// `Question: 1. yes (3), 2. no (5)`
+    fn standings(&self) -> String {
+        let options: Vec<_> = self
+            .options
+            .iter()
+            .zip(self.tally())
+            .enumerate()
+            .map(|(idx, (option, votes))| format!("{}. {option} ({votes})", idx + 1))
+            .collect();
+
+        format!("{}: {}", self.question, options.join(", "))
+    }
+
+    /// Announces the option with the most votes, or the ones that tied
+    fn result(&self) -> String {
+        let tally = self.tally();
+        let total = self.votes.len();
+        let most = tally.iter().copied().max().unwrap_or_default();
+        if most == 0 {
+            return format!("The poll \"{}\" ended without votes", self.question);
+        }
+
+        let winners: Vec<_> = self
+            .options
+            .iter()
+            .zip(&tally)
+            .filter(|(_, votes)| **votes == most)
+            .map(|(option, _)| option.as_str())
+            .collect();
+        let percent = most * 100 / total;
+        match winners.as_slice() {
+            [winner] => format!(
+                "The poll \"{}\" ended: {winner} won with {most} of {total} votes ({percent}%)",
+                self.question
+            ),
+            tied => format!(
+                "The poll \"{}\" ended in a tie between {} with {most} votes each",
+                self.question,
+                tied.join(" and ")
+            ),
+        }
+    }
+}
+
+/// The poll of every channel, there's at most one per channel
+#[derive(Debug, Default)]
+pub struct Polls {
+    polls: HashMap<String, Poll>,
+}
+
+impl Polls {
+    /// Counts a vote if the message is one, every viewer votes once
+    pub fn vote(&mut self, msg: &TwitchChatMsg) {
+        let Some(poll) = self.polls.get_mut(&msg.channel_name) else {
+            return;
+        };
+        if poll.votes.contains_key(&msg.login) {
+            return;
+        }
+
+        if let Some(idx) = poll.option(&msg.message) {
+            info!("<{}> voted for {} in #{}", msg.login, poll.options[idx], msg.channel_name);
+            poll.votes.insert(msg.login.clone(), idx);
+        }
+    }
+
+    /// Ends polls whose time is up, returns the channels and their results
+    pub fn finished(&mut self) -> Vec<(String, String)> {
+        let now = Instant::now();
+        let (finished, running): (HashMap<_, _>, _) = std::mem::take(&mut self.polls)
+            .into_iter()
+            .partition(|(_, poll)| poll.ends <= now);
+        self.polls = running;
+
+        finished
+            .into_iter()
+            .map(|(channel, poll)| (channel, poll.result()))
+            .collect()
+    }
+}
+
+/// Handles `!poll`, which shows the votes so far to anyone,
+/// and `!poll [seconds] "Question" | option | option ...` and `!poll end` for moderators
+///
+/// Returns the reply for chat, or None if the message wasn't a poll command
+pub fn poll_command(
+    msg: &TwitchChatMsg,
+    call: &CommandCall<'_>,
+    polls: &mut Polls,
+    style: &CommandStyle,
+    settings: &PollSettings,
+) -> Option<String> {
+    if !style.matches(&style.trigger("poll"), call.name) {
+        return None;
+    }
+
+    let user = &msg.display_name;
+    let channel = &msg.channel_name;
+    if call.args.is_empty() {
+        let poll = polls.polls.get(channel);
+        return Some(poll.map_or_else(
+            || "There's no poll right now".to_string(),
+            |poll| {
+                let left = poll.ends.saturating_duration_since(Instant::now()).as_secs();
+                format!("{} - {left}s left, vote with a number", poll.standings())
+            },
+        ));
+    }
+
+    if Permission::of(msg) < Permission::Moderator {
+        info!("<{}> tried to use {} without permission", user, call.name);
+        return None;
+    }
+
+    if call.args == ["end"] {
+        return Some(
+            polls
+                .polls
+                .remove(channel)
+                .map_or_else(|| format!("@{user} there's no poll to end"), |poll| poll.result()),
+        );
+    }
+    if polls.polls.contains_key(channel) {
+        return Some(format!("@{user} there's a poll already, end it first with {}poll end", style.prefix));
+    }
+
+    let (seconds, args) = match call.args.split_first() {
+        Some((seconds, rest)) if seconds.parse::<u64>().is_ok() => (seconds.parse().ok(), rest),
+        _ => (None, call.args.as_slice()),
+    };
+    let mut parts = args.join(" ");
+    parts.retain(|c| c != '"');
+    let mut parts = parts.split('|').map(str::trim).filter(|part| !part.is_empty());
+    let question = parts.next().unwrap_or_default().to_string();
+    let options: Vec<_> = parts.map(ToString::to_string).collect();
+    let seconds = seconds.unwrap_or(settings.duration);
+    let valid = !question.is_empty()
+        && (MIN_OPTIONS..=MAX_OPTIONS).contains(&options.len())
+        && seconds <= MAX_DURATION;
+    let ends = Instant::now().checked_add(Duration::from_secs(seconds));
+    let Some(ends) = ends.filter(|_| valid) else {
+        return Some(format!(
+            "@{user} usage: {}poll [seconds] \"Question\" | option 1 | option 2 ... \
+             (up to {MAX_OPTIONS} options and {MAX_DURATION} seconds)",
+            style.prefix
+        ));
+    };
+
+    info!("<{}> started a poll in #{}: {}", user, channel, question);
+    let poll = Poll {
+        question,
+        options,
+        votes: HashMap::new(),
+        ends,
+    };
+    let reply = format!("Poll for {seconds}s! {} - vote with a number", poll.standings());
+    polls.polls.insert(channel.clone(), poll);
+
+    Some(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_vote_per_viewer() {
+        let style = CommandStyle::default();
+        let mut polls = Polls::default();
+        let msg = TwitchChatMsg::for_test("neon", "moderator/1", "mod", r#"!poll 0 "Which language?" | Rust | C++ | Go"#);
+        let call = style.parse(&msg.message).unwrap();
+        let started = poll_command(&msg, &call, &mut polls, &style, &PollSettings::default()).unwrap();
+        assert_eq!(started, "Poll for 0s! Which language?: 1. Rust (0), 2. C++ (0), 3. Go (0) - vote with a number");
+
+        let viewer = |login, message| TwitchChatMsg::for_test("neon", "", login, message);
+        polls.vote(&viewer("alice", "1"));
+        polls.vote(&viewer("alice", "2"));
+        polls.vote(&viewer("bob", "rust"));
+        polls.vote(&viewer("carol", "c++"));
+        polls.vote(&viewer("dave", "4"));
+        assert_eq!(
+            polls.polls["neon"].standings(),
+            "Which language?: 1. Rust (2), 2. C++ (1), 3. Go (0)"
+        );
+
+        assert_eq!(
+            polls.finished(),
+            [(
+                "neon".to_string(),
+                "The poll \"Which language?\" ended: Rust won with 2 of 3 votes (66%)".to_string()
+            )]
+        );
+        assert!(polls.polls.is_empty());
+    }
+
+    #[test]
+    fn durations_longer_than_a_day_are_rejected() {
+        let style = CommandStyle::default();
+        let mut polls = Polls::default();
+        let msg = TwitchChatMsg::for_test("neon", "moderator/1", "mod", r#"!poll 18446744073709551615 "Q" | a | b"#);
+        let call = style.parse(&msg.message).unwrap();
+
+        let reply = poll_command(&msg, &call, &mut polls, &style, &PollSettings::default()).unwrap();
+        assert!(reply.starts_with("@mod usage:"));
+        assert!(polls.polls.is_empty());
+    }
+}
@@ -0,0 +1,159 @@
+use log::info;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use crate::twitch::chat_bot::json_file::{load_or_default, save_atomic};
+use crate::twitch::chat_bot::msg_parser::TwitchChatMsg;
+
+/// What happens to a chatter who broke a filter
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Punishment {
+    /// Delete the message
+    Delete,
+    /// Delete the message and tell the chatter why
+    Warn(String),
+    /// Time the chatter out for some seconds
+    Timeout(u64),
+    Ban,
+}
+
+impl Punishment {
+    /// The chat commands (and messages) that carry out the punishment
+    #[must_use]
+    pub fn commands(&self, msg: &TwitchChatMsg, reason: &str) -> Vec<String> {
+        let delete = Some(&msg.id)
+            .filter(|id| !id.is_empty())
+            .map(|id| format!("/delete {id}"));
+
+        match self {
+            Self::Delete => delete.into_iter().collect(),
+            Self::Warn(warning) => delete
+                .into_iter()
+                .chain(Some(format!("@{} {warning} ({reason})", msg.display_name)))
+                .collect(),
+            Self::Timeout(seconds) => vec![format!("/timeout {} {seconds} {reason}", msg.login)],
+            Self::Ban => vec![format!("/ban {} {reason}", msg.login)],
+        }
+    }
+}
+
+/// Punishments that get harsher for every offence within a window,
+/// the `moderation.ladder` setting
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Ladder {
+    /// The nth offence gets the nth step, the last step repeats
+    pub steps: Vec<Punishment>,
+    /// Seconds an offence counts towards the next step
+    pub window: u64,
+    /// Where offences are saved to survive a restart, they're only kept in memory if unset
+    pub history_file: Option<PathBuf>,
+}
+
+impl Default for Ladder {
+    fn default() -> Self {
+        Self {
+            steps: vec![
+                Punishment::Warn("please follow the chat rules".to_string()),
+                Punishment::Timeout(60),
+                Punishment::Timeout(600),
+                Punishment::Ban,
+            ],
+            window: 24 * 60 * 60,
+            history_file: None,
+        }
+    }
+}
+
+impl Ladder {
+    /// The punishment for an offence that was just recorded
+    #[must_use]
+    pub fn step(&self, offences: usize) -> Punishment {
+        self.steps
+            .get(offences.saturating_sub(1))
+            .or_else(|| self.steps.last())
+            .cloned()
+            .unwrap_or(Punishment::Delete)
+    }
+}
+
+/// When chatters broke a filter, per channel and login
+#[derive(Debug, Default)]
+pub struct Offences {
+    path: Option<PathBuf>,
+    history: BTreeMap<String, BTreeMap<String, Vec<SystemTime>>>,
+}
+
+impl Offences {
+    /// Reads the offence history, a missing file has no offences yet
+    /// # Errors
+    /// Returns an error if the file exists but can't be read or parsed
+    pub fn load(path: Option<PathBuf>) -> io::Result<Self> {
+        let history = match &path {
+            Some(path) => load_or_default(path)?,
+            None => BTreeMap::new(),
+        };
+
+        Ok(Self { path, history })
+    }
+
+    #[must_use]
+    pub const fn path(&self) -> Option<&PathBuf> {
+        self.path.as_ref()
+    }
+
+    /// Records an offence and returns how many the chatter has within the window,
+    /// older offences are forgotten
+    pub fn record(&mut self, channel: &str, login: &str, window: Duration) -> usize {
+        let now = SystemTime::now();
+        let offences = self
+            .history
+            .entry(channel.to_string())
+            .or_default()
+            .entry(login.to_string())
+            .or_default();
+        offences.retain(|offence| now.duration_since(*offence).map_or(true, |age| age < window));
+        offences.push(now);
+        let count = offences.len();
+        info!("<{}> has {} offences in #{}", login, count, channel);
+
+        count
+    }
+
+    /// Writes the history via a temporary file like the commands file, if there's a history file
+    /// # Errors
+    /// Returns an error if the history file couldn't be written
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        save_atomic(path, &self.history)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offences_climb_the_ladder() {
+        let ladder = Ladder::default();
+        let mut offences = Offences::default();
+        let window = Duration::from_secs(ladder.window);
+
+        let steps: Vec<_> = (0..5)
+            .map(|_| ladder.step(offences.record("neon", "bob", window)))
+            .collect();
+        assert!(matches!(steps[0], Punishment::Warn(_)));
+        assert_eq!(steps[1..], [Punishment::Timeout(60), Punishment::Timeout(600), Punishment::Ban, Punishment::Ban]);
+
+        // Offences outside the window don't count
+        assert_eq!(offences.record("neon", "bob", Duration::ZERO), 1);
+        assert_eq!(offences.record("other", "bob", window), 1);
+    }
+}
@@ -0,0 +1,280 @@
+use log::{error, info};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::twitch::chat_bot::command_parser::{CommandCall, CommandStyle};
+use crate::twitch::chat_bot::json_file::{load_or_default, save_atomic};
+use crate::twitch::chat_bot::msg_parser::TwitchChatMsg;
+use crate::twitch::chat_bot::permissions::Permission;
+
+/// A quote from stream, ids are counted per channel
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Quote {
+    pub id: u64,
+    pub channel: String,
+    pub text: String,
+    /// Who said it, the broadcaster unless given when the quote was added
+    pub author: String,
+    pub added_by: String,
+    /// What was being played, if it was known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub game: Option<String>,
+    /// `YYYY-MM-DD`
+    pub date: String,
+}
+
+impl std::fmt::Display for Quote {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}: \"{}\" - {}", self.id, self.text, self.author)?;
+        if let Some(game) = &self.game {
+            write!(f, " [{game}]")?;
+        }
+        write!(f, " ({})", self.date)
+    }
+}
+
+/// Quotes of every channel, saved to the quotes file on every change
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[allow(clippy::struct_field_names)]
+pub struct Quotes {
+    #[serde(skip)]
+    path: PathBuf,
+    /// The id of the next quote of each channel, so ids of deleted quotes aren't used again
+    #[serde(default)]
+    next_ids: BTreeMap<String, u64>,
+    quotes: Vec<Quote>,
+}
+
+impl Quotes {
+    /// Reads the quotes file
+    /// # Errors
+    /// Returns an error if the file exists but can't be read or parsed
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let quotes: Self = load_or_default(&path)?;
+
+        Ok(Self { path, ..quotes })
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn channel(&self, channel: &str) -> impl Iterator<Item = &Quote> {
+        let channel = channel.to_string();
+        self.quotes.iter().filter(move |quote| quote.channel == channel)
+    }
+
+    #[must_use]
+    pub fn get(&self, channel: &str, id: u64) -> Option<&Quote> {
+        self.channel(channel).find(|quote| quote.id == id)
+    }
+
+    #[must_use]
+    pub fn random(&self, channel: &str) -> Option<&Quote> {
+        let quotes: Vec<_> = self.channel(channel).collect();
+        quotes.choose(&mut rand::thread_rng()).copied()
+    }
+
+    /// Quotes containing the text, ignoring case
+    #[must_use]
+    pub fn search(&self, channel: &str, text: &str) -> Vec<&Quote> {
+        let text = text.to_lowercase();
+        self.channel(channel)
+            .filter(|quote| quote.text.to_lowercase().contains(&text))
+            .collect()
+    }
+
+    /// Adds a quote with the next id of its channel and saves the quotes file
+    /// # Errors
+    /// Returns an error if the quotes file couldn't be written, the quote is kept anyway
+    pub fn add(&mut self, mut quote: Quote) -> io::Result<u64> {
+        let newest = self.channel(&quote.channel).map(|quote| quote.id).max().unwrap_or_default();
+        let next_id = self.next_ids.entry(quote.channel.clone()).or_default();
+        let id = (*next_id).max(newest + 1);
+        *next_id = id + 1;
+        quote.id = id;
+        self.quotes.push(quote);
+
+        self.save().map(|()| id)
+    }
+
+    /// Removes a quote and saves the quotes file, false if there's no quote with the id
+    /// # Errors
+    /// Returns an error if the quotes file couldn't be written
+    pub fn remove(&mut self, channel: &str, id: u64) -> io::Result<bool> {
+        let count = self.quotes.len();
+        self.quotes
+            .retain(|quote| quote.channel != channel || quote.id != id);
+        if self.quotes.len() == count {
+            return Ok(false);
+        }
+
+        self.save().map(|()| true)
+    }
+
+    /// Writes the quotes of a channel to a JSON file
+    /// # Errors
+    /// Returns an error if the file couldn't be written
+    pub fn export(&self, channel: &str, path: &Path) -> io::Result<usize> {
+        let quotes: Vec<_> = self.channel(channel).collect();
+        fs::write(path, serde_json::to_string_pretty(&quotes)? + "\n")?;
+
+        Ok(quotes.len())
+    }
+
+    fn save(&self) -> io::Result<()> {
+        save_atomic(&self.path, self)
+    }
+}
+
+/// Handles `!quote [id | search <text> | export]`, `!addquote [@author] <text>` and `!delquote <id>`
+///
+/// Anyone can read quotes, adding, deleting and exporting them is for moderators
+/// Returns the reply for chat, or None if the message wasn't a quote command
+pub fn quote_command(
+    msg: &TwitchChatMsg,
+    call: &CommandCall<'_>,
+    quotes: &mut Quotes,
+    style: &CommandStyle,
+    game: Option<&str>,
+) -> Option<String> {
+    let action = ["quote", "addquote", "delquote"]
+        .iter()
+        .copied()
+        .find(|action| style.matches(&style.trigger(action), call.name))?;
+
+    let user = &msg.display_name;
+    let channel = &msg.channel_name;
+    let is_mod = Permission::of(msg) >= Permission::Moderator;
+
+    let reply = match (action, call.args.first().copied()) {
+        ("quote", None) => quotes
+            .random(channel)
+            .map_or_else(|| "There are no quotes yet".to_string(), ToString::to_string),
+        ("quote", Some("search")) => {
+            let text = call.args[1..].join(" ");
+            if text.is_empty() {
+                return Some(format!("@{user} usage: {}quote search <text>", style.prefix));
+            }
+            match quotes.search(channel, &text).as_slice() {
+                [] => format!("@{user} no quotes with \"{text}\""),
+                [quote] => quote.to_string(),
+                [quote, rest @ ..] => {
+                    let ids: Vec<_> = rest.iter().map(|quote| format!("#{}", quote.id)).collect();
+                    format!("{quote} (also {})", ids.join(", "))
+                }
+            }
+        }
+        ("quote", Some("export")) if is_mod => {
+            let path = PathBuf::from(format!("quotes-{channel}.json"));
+            match quotes.export(channel, &path) {
+                Ok(count) => format!("@{user} exported {count} quotes to {}", path.display()),
+                Err(err) => {
+                    error!("Could not export quotes to {}: {}", path.display(), err);
+                    format!("@{user} the quotes couldn't be exported")
+                }
+            }
+        }
+        ("quote", Some(id)) => id.trim_start_matches('#').parse().map_or_else(
+            |_| format!("@{user} usage: {}quote [id | search <text>]", style.prefix),
+            |id| {
+                quotes
+                    .get(channel, id)
+                    .map_or_else(|| format!("@{user} there's no quote #{id}"), ToString::to_string)
+            },
+        ),
+        _ if !is_mod => {
+            info!("<{}> tried to use {} without permission", user, call.name);
+            return None;
+        }
+        ("addquote", _) => {
+            let (author, text) = match call.args.first() {
+                Some(author) if author.starts_with('@') && call.args.len() > 1 => {
+                    (author.trim_start_matches('@').to_string(), call.args[1..].join(" "))
+                }
+                _ => (channel.clone(), call.args.join(" ")),
+            };
+            if text.is_empty() {
+                return Some(format!("@{user} usage: {}addquote [@author] <text>", style.prefix));
+            }
+
+            let quote = Quote {
+                id: 0,
+                channel: channel.clone(),
+                text: text.trim_matches('"').to_string(),
+                author,
+                added_by: msg.login.clone(),
+                game: game.map(ToString::to_string),
+                date: chrono::Local::now().format("%Y-%m-%d").to_string(),
+            };
+            match quotes.add(quote) {
+                Ok(id) => format!("@{user} added quote #{id}"),
+                Err(err) => {
+                    error!("Could not save quotes to {}: {}", quotes.path().display(), err);
+                    format!("@{user} added the quote, but it couldn't be saved and will be lost on restart")
+                }
+            }
+        }
+        (_, id) => {
+            let Some(id) = id.and_then(|id| id.trim_start_matches('#').parse().ok()) else {
+                return Some(format!("@{user} usage: {}delquote <id>", style.prefix));
+            };
+            match quotes.remove(channel, id) {
+                Ok(true) => format!("@{user} deleted quote #{id}"),
+                Ok(false) => format!("@{user} there's no quote #{id}"),
+                Err(err) => {
+                    error!("Could not save quotes to {}: {}", quotes.path().display(), err);
+                    format!("@{user} deleted quote #{id}, but it couldn't be saved and will be back on restart")
+                }
+            }
+        }
+    };
+
+    Some(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::twitch::chat_bot::json_file::TempPath;
+
+    fn quote(channel: &str, text: &str) -> Quote {
+        Quote {
+            id: 0,
+            channel: channel.to_string(),
+            text: text.to_string(),
+            author: channel.to_string(),
+            added_by: "bob".to_string(),
+            game: None,
+            date: "2020-11-05".to_string(),
+        }
+    }
+
+    #[test]
+    fn ids_are_per_channel() {
+        let path = TempPath::new("quotes.json");
+        let mut quotes = Quotes::load(path.to_path_buf()).unwrap();
+
+        assert_eq!(quotes.add(quote("neon", "It compiles!")).unwrap(), 1);
+        assert_eq!(quotes.add(quote("neon", "It doesn't compile")).unwrap(), 2);
+        assert_eq!(quotes.add(quote("other", "Hello")).unwrap(), 1);
+        assert_eq!(quotes.search("neon", "COMPILE").len(), 2);
+        assert!(quotes.remove("neon", 1).unwrap());
+        assert!(quotes.get("neon", 1).is_none());
+        assert_eq!(
+            quotes.get("neon", 2).unwrap().to_string(),
+            "#2: \"It doesn't compile\" - neon (2020-11-05)"
+        );
+
+        let mut reloaded = Quotes::load(path.to_path_buf()).unwrap();
+        assert_eq!(reloaded.quotes, quotes.quotes);
+
+        assert!(reloaded.remove("neon", 2).unwrap());
+        assert_eq!(reloaded.add(quote("neon", "New")).unwrap(), 3);
+    }
+}
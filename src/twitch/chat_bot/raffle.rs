@@ -0,0 +1,495 @@
+use chrono::Local;
+use log::{error, info};
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::twitch::chat_bot::command_parser::{CommandCall, CommandStyle};
+use crate::twitch::chat_bot::msg_parser::TwitchChatMsg;
+use crate::twitch::chat_bot::permissions::{is_subscriber, Permission};
+use crate::twitch::chat_bot::points::Points;
+
+/// Subscribers can't get more tickets than this
+const MAX_SUB_LUCK: u64 = 100;
+
+/// Defaults for raffles and where their results go, the `raffle` setting
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct RaffleSettings {
+    /// Tickets a subscriber gets, everyone else gets one
+    pub sub_luck: u64,
+    /// Points it costs to enter
+    pub cost: u64,
+    /// Seconds a winner has to say something in chat to claim the prize
+    pub claim_seconds: u64,
+    /// Every winner is appended to this file as a JSON line
+    pub results_file: PathBuf,
+}
+
+impl RaffleSettings {
+    /// Reads the `raffle` setting, the defaults are used if it's missing
+    /// # Errors
+    /// Returns an error if the setting has the wrong type or `sub_luck` is too high
+    pub fn from_config(settings: &config::Config) -> Result<Self, config::ConfigError> {
+        let raffle = match settings.get::<Self>("raffle") {
+            Err(config::ConfigError::NotFound(_)) => Self::default(),
+            raffle => raffle?,
+        };
+        if raffle.sub_luck > MAX_SUB_LUCK {
+            return Err(config::ConfigError::Message(format!(
+                "raffle: sub_luck can't be more than {MAX_SUB_LUCK}"
+            )));
+        }
+
+        Ok(raffle)
+    }
+}
+
+impl Default for RaffleSettings {
+    fn default() -> Self {
+        Self {
+            sub_luck: 1,
+            cost: 0,
+            claim_seconds: 60,
+            results_file: "raffles.jsonl".into(),
+        }
+    }
+}
+
+/// A line of the results file
+#[derive(Serialize, Debug)]
+struct RaffleResult<'a> {
+    time: String,
+    channel: &'a str,
+    keyword: &'a str,
+    entries: usize,
+    winner: &'a str,
+    claimed: bool,
+}
+
+#[derive(Debug)]
+struct Entry {
+    login: String,
+    display_name: String,
+    tickets: u64,
+}
+
+#[derive(Debug)]
+struct Winner {
+    login: String,
+    display_name: String,
+    drawn: Instant,
+}
+
+/// A raffle in a channel, chatters enter by sending the keyword
+#[derive(Debug)]
+struct Raffle {
+    keyword: String,
+    cost: u64,
+    sub_luck: u64,
+    open: bool,
+    /// Everyone who entered and hasn't won yet
+    entries: Vec<Entry>,
+    /// How many entered, winners included
+    entered: usize,
+    /// Drawn winners who haven't claimed their prize yet
+    pending: Vec<Winner>,
+}
+
+impl Raffle {
+    /// Picks an entry, every ticket has the same chance, and removes it
+    fn draw_entry(&mut self, rng: &mut impl Rng) -> Option<Entry> {
+        let tickets = self
+            .entries
+            .iter()
+            .fold(0_u64, |tickets, entry| tickets.saturating_add(entry.tickets));
+        if tickets == 0 {
+            return None;
+        }
+
+        let mut ticket = rng.gen_range(0, tickets);
+        let idx = self.entries.iter().position(|entry| {
+            if ticket < entry.tickets {
+                return true;
+            }
+            ticket -= entry.tickets;
+            false
+        })?;
+
+        Some(self.entries.remove(idx))
+    }
+
+    /// Closes the raffle and draws up to `count` winners who still have to claim their prize,
+    /// returns their names
+    fn draw(&mut self, count: usize) -> Vec<String> {
+        self.open = false;
+
+        let mut rng = thread_rng();
+        let winners: Vec<_> = (0..count.min(self.entries.len()))
+            .map_while(|_| self.draw_entry(&mut rng))
+            .collect();
+        let names = winners.iter().map(|entry| entry.display_name.clone()).collect();
+        self.pending.extend(winners.into_iter().map(|entry| Winner {
+            login: entry.login,
+            display_name: entry.display_name,
+            drawn: Instant::now(),
+        }));
+
+        names
+    }
+
+    /// Gives everyone who entered and didn't claim a prize their points back
+    fn refund(&self, channel: &str, points: &mut Points) {
+        if self.cost == 0 {
+            return;
+        }
+
+        let cost = i64::try_from(self.cost).unwrap_or(i64::MAX);
+        let entrants = self
+            .entries
+            .iter()
+            .map(|entry| &entry.login)
+            .chain(self.pending.iter().map(|winner| &winner.login));
+        for login in entrants {
+            if let Err(err) = points.add(channel, login, cost) {
+                error!("Could not save points to {}: {}", points.path().display(), err);
+            }
+        }
+    }
+
+    /// Appends a winner to the results file, errors are only logged
+    fn log_result(&self, path: &Path, channel: &str, winner: &str, claimed: bool) {
+        let result = RaffleResult {
+            time: Local::now().to_rfc3339(),
+            channel,
+            keyword: &self.keyword,
+            entries: self.entered,
+            winner,
+            claimed,
+        };
+        if let Err(err) = append(path, &result) {
+            error!("Could not write raffle results to {}: {}", path.display(), err);
+        }
+    }
+}
+
+fn append(path: &Path, result: &RaffleResult<'_>) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(result)?)
+}
+
+/// The raffle of every channel, there's at most one per channel
+#[derive(Debug, Default)]
+pub struct Raffles {
+    raffles: HashMap<String, Raffle>,
+}
+
+impl Raffles {
+    /// Enters chatters who send the keyword, and lets drawn winners claim their prize
+    ///
+    /// Returns a reply for chat if there's something to say
+    pub fn chatted(
+        &mut self,
+        msg: &TwitchChatMsg,
+        points: &mut Points,
+        settings: &RaffleSettings,
+        points_name: &str,
+    ) -> Option<String> {
+        let channel = &msg.channel_name;
+        let raffle = self.raffles.get_mut(channel)?;
+        let user = &msg.display_name;
+
+        if let Some(idx) = raffle.pending.iter().position(|winner| winner.login == msg.login) {
+            let winner = raffle.pending.remove(idx);
+            info!("<{}> claimed the raffle in #{}", winner.login, channel);
+            raffle.log_result(&settings.results_file, channel, &winner.login, true);
+            if !raffle.open && raffle.pending.is_empty() && raffle.entries.is_empty() {
+                self.raffles.remove(channel);
+            }
+            return Some(format!("@{user} claimed the prize, congratulations!"));
+        }
+
+        if !raffle.open
+            || !msg.message.trim().eq_ignore_ascii_case(&raffle.keyword)
+            || raffle.entries.iter().any(|entry| entry.login == msg.login)
+        {
+            return None;
+        }
+        if raffle.cost > 0 {
+            let balance = points.get(channel, &msg.login);
+            if balance < raffle.cost {
+                return Some(format!(
+                    "@{user} entering costs {} {points_name}, you have {balance}",
+                    raffle.cost
+                ));
+            }
+            let cost = i64::try_from(raffle.cost).unwrap_or(i64::MAX);
+            if let Err(err) = points.add(channel, &msg.login, -cost) {
+                error!("Could not save points to {}: {}", points.path().display(), err);
+            }
+        }
+
+        let tickets = if is_subscriber(msg) { raffle.sub_luck } else { 1 };
+        info!("<{}> entered the raffle in #{} with {} tickets", msg.login, channel, tickets);
+        raffle.entries.push(Entry {
+            login: msg.login.clone(),
+            display_name: msg.display_name.clone(),
+            tickets,
+        });
+        raffle.entered += 1;
+
+        None
+    }
+
+    /// Drops winners who didn't claim their prize in time
+    ///
+    /// Returns the channels and messages telling them
+    pub fn expired(&mut self, settings: &RaffleSettings, style: &CommandStyle) -> Vec<(String, String)> {
+        let claim_time = Duration::from_secs(settings.claim_seconds);
+        let mut messages = Vec::new();
+
+        for (channel, raffle) in &mut self.raffles {
+            let (expired, pending) = raffle
+                .pending
+                .drain(..)
+                .partition(|winner| winner.drawn.elapsed() >= claim_time);
+            raffle.pending = pending;
+
+            for winner in expired {
+                info!("<{}> didn't claim the raffle in #{}", winner.login, channel);
+                raffle.log_result(&settings.results_file, channel, &winner.login, false);
+                messages.push((
+                    channel.clone(),
+                    format!(
+                        "{} didn't claim the prize in time, draw someone else with {}raffle draw",
+                        winner.display_name, style.prefix
+                    ),
+                ));
+            }
+        }
+
+        messages
+    }
+}
+
+/// Handles `!raffle`, which shows the raffle to anyone,
+/// and `!raffle open <keyword> [cost] [sub luck]`, `!raffle close`, `!raffle draw [count]` and `!raffle cancel`
+/// for moderators
+///
+/// Returns the reply for chat, or None if the message wasn't a raffle command
+pub fn raffle_command(
+    msg: &TwitchChatMsg,
+    call: &CommandCall<'_>,
+    raffles: &mut Raffles,
+    points: &mut Points,
+    style: &CommandStyle,
+    settings: &RaffleSettings,
+    points_name: &str,
+) -> Option<String> {
+    if !style.matches(&style.trigger("raffle"), call.name) {
+        return None;
+    }
+
+    let user = &msg.display_name;
+    let channel = &msg.channel_name;
+    let action = call.args.first().map(|action| action.to_lowercase());
+    let Some(action) = action else {
+        let raffle = raffles.raffles.get(channel).filter(|raffle| raffle.open);
+        return Some(raffle.map_or_else(
+            || "There's no raffle right now".to_string(),
+            |raffle| {
+                let cost = cost_text(raffle.cost, points_name);
+                format!("Send {} to enter the raffle{cost}! {} entered so far", raffle.keyword, raffle.entered)
+            },
+        ));
+    };
+
+    if Permission::of(msg) < Permission::Moderator {
+        info!("<{}> tried to use {} without permission", user, call.name);
+        return None;
+    }
+    let usage = || {
+        Some(format!(
+            "@{user} usage: {}raffle [open <keyword> [cost] [sub luck up to {MAX_SUB_LUCK}] | close | draw [count] | cancel]",
+            style.prefix
+        ))
+    };
+
+    let reply = match action.as_str() {
+        "open" => {
+            if raffles.raffles.get(channel).is_some_and(|raffle| raffle.open) {
+                return Some(format!("@{user} there's a raffle already, close or cancel it first"));
+            }
+            let Some(keyword) = call.args.get(1) else {
+                return usage();
+            };
+            let number = |idx: usize, default| call.args.get(idx).map_or(Some(default), |arg| arg.parse().ok());
+            let sub_luck = number(3, settings.sub_luck).filter(|luck| *luck <= MAX_SUB_LUCK);
+            let (Some(cost), Some(sub_luck)) = (number(2, settings.cost), sub_luck) else {
+                return usage();
+            };
+
+            info!("<{}> opened a raffle in #{} with {}", user, channel, keyword);
+            raffles.raffles.insert(
+                channel.clone(),
+                Raffle {
+                    keyword: (*keyword).to_string(),
+                    cost,
+                    sub_luck: sub_luck.max(1),
+                    open: true,
+                    entries: Vec::new(),
+                    entered: 0,
+                    pending: Vec::new(),
+                },
+            );
+            let cost = cost_text(cost, points_name);
+            let luck = if sub_luck > 1 {
+                format!(", subscribers get {sub_luck} tickets")
+            } else {
+                String::new()
+            };
+            format!("A raffle is open! Send {keyword} to enter{cost}{luck}")
+        }
+        "close" => match raffles.raffles.get_mut(channel).filter(|raffle| raffle.open) {
+            Some(raffle) => {
+                raffle.open = false;
+                format!("The raffle is closed with {} entries, good luck!", raffle.entered)
+            }
+            None => format!("@{user} there's no open raffle"),
+        },
+        "draw" => {
+            let Some(raffle) = raffles.raffles.get_mut(channel) else {
+                return Some(format!("@{user} there's no raffle to draw from"));
+            };
+            let Some(count) = call.args.get(1).map_or(Some(1), |count| count.parse::<usize>().ok()) else {
+                return usage();
+            };
+
+            let winners = raffle.draw(count.max(1));
+            if winners.is_empty() {
+                return Some(format!("@{user} there's nobody left to draw"));
+            }
+            info!("Raffle winners in #{}: {}", channel, winners.join(", "));
+            format!(
+                "@{} won the raffle! Say something in chat within {} seconds to claim the prize",
+                winners.join(", @"),
+                settings.claim_seconds
+            )
+        }
+        "cancel" => {
+            let Some(raffle) = raffles.raffles.remove(channel) else {
+                return Some(format!("@{user} there's no raffle to cancel"));
+            };
+            raffle.refund(channel, points);
+            info!("<{}> cancelled the raffle in #{}", user, channel);
+            "The raffle was cancelled".to_string()
+        }
+        _ => return usage(),
+    };
+
+    Some(reply)
+}
+
+/// ` for 100 points`, empty for free raffles
+fn cost_text(cost: u64, points_name: &str) -> String {
+    if cost > 0 {
+        format!(" for {cost} {points_name}")
+    } else {
+        String::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::twitch::chat_bot::json_file::TempPath;
+
+    #[test]
+    fn entries_cost_points_and_winners_claim() {
+        let points_path = TempPath::new("raffle-points.json");
+        let results_file = TempPath::new("raffle-results.jsonl");
+        let settings = RaffleSettings {
+            results_file: results_file.to_path_buf(),
+            ..RaffleSettings::default()
+        };
+        let style = CommandStyle::default();
+        let mut points = Points::load(points_path.to_path_buf()).unwrap();
+        points.add("neon", "alice", 100).unwrap();
+        let mut raffles = Raffles::default();
+
+        let chat = |badges, login, message| TwitchChatMsg::for_test("neon", badges, login, message);
+        let command = |msg: &TwitchChatMsg, raffles: &mut Raffles, points: &mut Points| {
+            let call = style.parse(&msg.message).unwrap();
+            raffle_command(msg, &call, raffles, points, &style, &settings, "points")
+        };
+        let lucky = command(&chat("moderator/1", "mod", "!raffle open !join 0 18446744073709551615"), &mut raffles, &mut points);
+        assert!(lucky.unwrap().starts_with("@mod usage:"));
+        let opened = command(&chat("moderator/1", "mod", "!raffle open !join 50"), &mut raffles, &mut points);
+        assert_eq!(opened.unwrap(), "A raffle is open! Send !join to enter for 50 points");
+        assert!(command(&chat("", "bob", "!raffle close"), &mut raffles, &mut points).is_none());
+
+        // Bob can't afford it, Alice enters once
+        assert!(raffles
+            .chatted(&chat("", "bob", "!join"), &mut points, &settings, "points")
+            .is_some());
+        for _ in 0..2 {
+            raffles.chatted(&chat("", "alice", "!JOIN"), &mut points, &settings, "points");
+        }
+        assert_eq!(points.get("neon", "alice"), 50);
+
+        let drawn = command(&chat("moderator/1", "mod", "!raffle draw 3"), &mut raffles, &mut points).unwrap();
+        assert!(drawn.starts_with("@alice won the raffle!"));
+        let claimed = raffles.chatted(&chat("", "alice", "me!"), &mut points, &settings, "points");
+        assert_eq!(claimed.unwrap(), "@alice claimed the prize, congratulations!");
+        assert!(raffles.raffles.is_empty());
+
+        let results = std::fs::read_to_string(&settings.results_file).unwrap();
+        assert!(results.contains(r#""winner":"alice","claimed":true"#));
+    }
+
+    #[test]
+    fn subscribers_and_founders_get_more_tickets() {
+        let settings = RaffleSettings::default();
+        let style = CommandStyle::default();
+        let mut points = Points::default();
+        let mut raffles = Raffles::default();
+
+        let open = TwitchChatMsg::for_test("neon", "moderator/1", "mod", "!raffle open !join 0 3");
+        let call = style.parse(&open.message).unwrap();
+        assert!(raffle_command(&open, &call, &mut raffles, &mut points, &style, &settings, "points").is_some());
+        for (badges, login) in [("founder/0", "alice"), ("subscriber/12", "bob"), ("", "carol")] {
+            let join = TwitchChatMsg::for_test("neon", badges, login, "!join");
+            raffles.chatted(&join, &mut points, &settings, "points");
+        }
+
+        let tickets: Vec<_> = raffles.raffles["neon"].entries.iter().map(|entry| entry.tickets).collect();
+        assert_eq!(tickets, [3, 3, 1]);
+    }
+
+    #[test]
+    fn every_ticket_has_a_chance() {
+        let mut raffle = Raffle {
+            keyword: "!join".to_string(),
+            cost: 0,
+            sub_luck: 3,
+            open: false,
+            entries: vec![
+                Entry { login: "alice".to_string(), display_name: "Alice".to_string(), tickets: 1 },
+                Entry { login: "bob".to_string(), display_name: "Bob".to_string(), tickets: 3 },
+            ],
+            entered: 2,
+            pending: Vec::new(),
+        };
+
+        // Drawing stops once everyone has won
+        let mut drawn = raffle.draw(usize::MAX);
+        drawn.sort();
+        assert_eq!(drawn, ["Alice", "Bob"]);
+        assert_eq!(raffle.pending.len(), 2);
+    }
+}
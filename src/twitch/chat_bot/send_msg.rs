@@ -1,19 +1,310 @@
 use native_tls::TlsStream;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::net::TcpStream;
+use std::time::{Duration, Instant};
 use tungstenite::stream::Stream;
 use tungstenite::{Message, WebSocket};
-use log::info;
+use log::{error, info};
 
+use crate::common_structs::socket::{Disconnected, Result};
+use crate::twitch::chat_bot::chat_log::{ChatLog, LogKind};
+
+/// Twitch drops messages longer than this
+const MAX_MSG_CHARS: usize = 500;
+/// Messages allowed per window, more are allowed where the bot is a moderator
+const MSG_LIMIT: usize = 20;
+const MOD_MSG_LIMIT: usize = 100;
+const LIMIT_WINDOW: Duration = Duration::from_secs(30);
+/// Appended to a message that's identical to the previous one,
+/// otherwise Twitch drops it as a duplicate
+const DUPLICATE_SUFFIX: &str = " \u{e0000}";
+
+/// Sends a message, as a reply in the thread of `reply_to` if it's the id of a chat message
+/// # Errors
+/// Returns `Disconnected` if the message couldn't be written to the socket
 pub fn send_msg(
     ws_chat: &mut WebSocket<Stream<TcpStream, TlsStream<TcpStream>>>,
-    channel_id: &String,
-    msg: String
-) {
-    // PRIVMSG #<channel> :This is a sample message
-    let msg_id = format!("PRIVMSG{} :", channel_id);
-
-    let msg = format!("{}{}", msg_id, msg);
+    channel_id: &str,
+    msg: &str,
+    reply_to: Option<&str>,
+) -> Result<()> {
+    let msg = privmsg_line(channel_id, msg, reply_to);
 
     info!("Sending message: {}", &msg);
-    ws_chat.write_message(Message::Text(msg)).unwrap()
+    ws_chat.write_message(Message::Text(msg)).map_err(|err| {
+        error!("Could not write chat msg to twitch:\n {}\n", err);
+        Disconnected
+    })
+}
+
+/// The IRC line for a chat message, `[@reply-parent-msg-id=<id> ]PRIVMSG #<channel> :<msg>`
+fn privmsg_line(channel_id: &str, msg: &str, reply_to: Option<&str>) -> String {
+    reply_to.map_or_else(
+        || format!("PRIVMSG #{channel_id} :{msg}"),
+        |id| format!("@reply-parent-msg-id={id} PRIVMSG #{channel_id} :{msg}"),
+    )
+}
+
+struct QueuedMsg {
+    channel: String,
+    text: String,
+    reply_to: Option<String>,
+}
+
+/// Queues outgoing chat messages and sends them within Twitch's rate limits
+#[derive(Default)]
+pub struct ChatSender {
+    queue: VecDeque<QueuedMsg>,
+    sent: VecDeque<Instant>,
+    last_sent: HashMap<String, (String, Instant)>,
+    mod_channels: HashSet<String>,
+    paused_until: Option<Instant>,
+    /// Sent messages are logged as the bot's nick
+    log: ChatLog,
+    nick: String,
+}
+
+impl ChatSender {
+    /// Queues a message, splitting it up if it's too long for Twitch
+    ///
+    /// Built-in commands like `!points` answer with this, so they never reply in a thread,
+    /// only commands from the commands file with `reply` do
+    pub fn queue(&mut self, channel: &str, msg: &str) {
+        self.queue_reply(channel, msg, None);
+    }
+
+    /// Queues a message that replies to the chat message with id `reply_to`, if there is one
+    pub fn queue_reply(&mut self, channel: &str, msg: &str, reply_to: Option<&str>) {
+        for text in split_msg(msg.trim()) {
+            self.queue.push_back(QueuedMsg {
+                channel: channel.to_string(),
+                text,
+                reply_to: reply_to.map(ToString::to_string),
+            });
+        }
+    }
+
+    /// Moderators (and the broadcaster) get a higher rate limit, known from USERSTATE
+    pub fn set_moderator(&mut self, channel: &str, is_mod: bool) {
+        if is_mod {
+            self.mod_channels.insert(channel.to_string());
+        } else {
+            self.mod_channels.remove(channel);
+        }
+    }
+
+    /// Where sent messages are logged
+    pub fn set_log(&mut self, log: ChatLog, nick: &str) {
+        self.log = log;
+        self.nick = nick.to_string();
+    }
+
+    /// Twitch dropped a message for sending too fast, nothing is sent for a while
+    pub fn rate_limited(&mut self) {
+        self.paused_until = Some(Instant::now() + LIMIT_WINDOW);
+    }
+
+    /// Sends as many queued messages as the rate limit allows
+    ///
+    /// Messages that fail to send are kept, and sent after reconnecting
+    /// # Errors
+    /// Returns `Disconnected` if a message couldn't be written to the socket
+    pub fn flush(&mut self, ws_chat: &mut WebSocket<Stream<TcpStream, TlsStream<TcpStream>>>) -> Result<()> {
+        self.flush_with(Instant::now(), |channel, text, reply_to| {
+            send_msg(ws_chat, channel, text, reply_to)
+        })
+    }
+
+    /// Sends queued messages with `write` until the rate limit is reached or a write fails
+    fn flush_with<W>(&mut self, now: Instant, mut write: W) -> Result<()>
+    where
+        W: FnMut(&str, &str, Option<&str>) -> Result<()>,
+    {
+        while let Some(text) = self.next_text(now) {
+            let Some(next) = self.queue.front() else {
+                break;
+            };
+            write(&next.channel, &text, next.reply_to.as_deref())?;
+            self.log.write(&next.channel, LogKind::Reply, &self.nick, &next.text);
+
+            self.sent.push_back(now);
+            if let Some(sent) = self.queue.pop_front() {
+                self.last_sent.insert(sent.channel, (text, now));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The text to send for the next queued message, if the rate limit allows sending it at `now`
+    fn next_text(&mut self, now: Instant) -> Option<String> {
+        if self.paused_until.is_some_and(|until| now < until) {
+            return None;
+        }
+        let next = self.queue.front()?;
+
+        while self
+            .sent
+            .front()
+            .is_some_and(|sent| now.saturating_duration_since(*sent) > LIMIT_WINDOW)
+        {
+            self.sent.pop_front();
+        }
+
+        let limit = if self.mod_channels.contains(&next.channel) {
+            MOD_MSG_LIMIT
+        } else {
+            MSG_LIMIT
+        };
+        if self.sent.len() >= limit {
+            return None;
+        }
+
+        let mut text = next.text.clone();
+        if let Some((last_text, last_time)) = self.last_sent.get(&next.channel) {
+            if *last_text == text && now.saturating_duration_since(*last_time) < LIMIT_WINDOW {
+                text.push_str(DUPLICATE_SUFFIX);
+            }
+        }
+
+        Some(text)
+    }
+}
+
+/// Splits a message into chunks Twitch accepts, preferring to split on spaces
+fn split_msg(msg: &str) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut rest = msg;
+
+    while rest.chars().count() > MAX_MSG_CHARS {
+        let limit = rest
+            .char_indices()
+            .nth(MAX_MSG_CHARS)
+            .map_or(rest.len(), |(idx, _)| idx);
+        let split_at = rest[..limit].rfind(' ').filter(|idx| *idx > 0).unwrap_or(limit);
+
+        chunks.push(rest[..split_at].trim_end().to_string());
+        rest = rest[split_at..].trim_start();
+    }
+
+    if !rest.is_empty() {
+        chunks.push(rest.to_string());
+    }
+
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_messages_are_split_on_spaces() {
+        let msg = "word ".repeat(150);
+        let chunks = split_msg(msg.trim());
+
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks.iter().all(|chunk| chunk.chars().count() <= MAX_MSG_CHARS));
+        assert!(chunks.iter().all(|chunk| chunk.starts_with("word") && chunk.ends_with("word")));
+        assert_eq!(split_msg("short"), vec!["short"]);
+    }
+
+    #[test]
+    fn replies_are_tagged_with_the_parent_id() {
+        assert_eq!(privmsg_line("neon", "hi there", None), "PRIVMSG #neon :hi there");
+        assert_eq!(
+            privmsg_line("neon", "hi there", Some("b34ccfc7-4977-403a-8a94-33c6bac34fb8")),
+            "@reply-parent-msg-id=b34ccfc7-4977-403a-8a94-33c6bac34fb8 PRIVMSG #neon :hi there"
+        );
+    }
+    /// Flushes at `now`, returning the lines that were sent
+    fn flush_at(sender: &mut ChatSender, now: Instant) -> Vec<String> {
+        let mut sent = Vec::new();
+        let flushed = sender.flush_with(now, |channel, text, reply_to| {
+            sent.push(privmsg_line(channel, text, reply_to));
+            Ok(())
+        });
+        assert!(flushed.is_ok());
+        sent
+    }
+
+    #[test]
+    fn sends_at_most_20_messages_per_30_seconds() {
+        let mut sender = ChatSender::default();
+        for i in 0..25 {
+            sender.queue("neon", &format!("msg {i}"));
+        }
+        let start = Instant::now();
+
+        assert_eq!(flush_at(&mut sender, start).len(), MSG_LIMIT);
+        assert!(flush_at(&mut sender, start + Duration::from_secs(29)).is_empty());
+        assert_eq!(sender.queue.len(), 5);
+
+        let sent = flush_at(&mut sender, start + Duration::from_secs(31));
+        assert_eq!(sent.first().map(String::as_str), Some("PRIVMSG #neon :msg 20"));
+        assert_eq!(sent.len(), 5);
+    }
+
+    #[test]
+    fn moderators_may_send_100_messages() {
+        let mut sender = ChatSender::default();
+        sender.set_moderator("neon", true);
+        for i in 0..105 {
+            sender.queue("neon", &format!("msg {i}"));
+        }
+
+        assert_eq!(flush_at(&mut sender, Instant::now()).len(), MOD_MSG_LIMIT);
+
+        sender.set_moderator("neon", false);
+        sender.queue("other", "hi");
+        assert!(flush_at(&mut sender, Instant::now()).is_empty());
+    }
+
+    #[test]
+    fn repeated_messages_get_a_suffix() {
+        let mut sender = ChatSender::default();
+        sender.queue("neon", "same");
+        sender.queue("neon", "same");
+        sender.queue("other", "same");
+        let start = Instant::now();
+
+        assert_eq!(
+            flush_at(&mut sender, start),
+            [
+                "PRIVMSG #neon :same".to_string(),
+                format!("PRIVMSG #neon :same{DUPLICATE_SUFFIX}"),
+                "PRIVMSG #other :same".to_string(),
+            ]
+        );
+
+        sender.queue("neon", "same");
+        assert_eq!(flush_at(&mut sender, start + Duration::from_secs(31)), ["PRIVMSG #neon :same"]);
+    }
+
+    #[test]
+    fn nothing_is_sent_after_being_rate_limited() {
+        let mut sender = ChatSender::default();
+        sender.rate_limited();
+        sender.queue("neon", "hi");
+
+        assert!(flush_at(&mut sender, Instant::now()).is_empty());
+        assert_eq!(
+            flush_at(&mut sender, Instant::now() + LIMIT_WINDOW + Duration::from_secs(1)),
+            ["PRIVMSG #neon :hi"]
+        );
+    }
+
+    #[test]
+    fn failed_messages_stay_queued() {
+        let mut sender = ChatSender::default();
+        sender.queue("neon", "first");
+        sender.queue("neon", "second");
+        let now = Instant::now();
+
+        assert!(sender.flush_with(now, |_, _, _| Err(Disconnected)).is_err());
+        assert_eq!(sender.queue.len(), 2);
+        assert!(sender.sent.is_empty());
+
+        assert_eq!(flush_at(&mut sender, now), ["PRIVMSG #neon :first", "PRIVMSG #neon :second"]);
+    }
 }
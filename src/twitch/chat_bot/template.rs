@@ -0,0 +1,222 @@
+use rand::distributions::Uniform;
+use rand::{thread_rng, Rng};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::twitch::stream_status::Uptime;
+
+/// Values available to `{variables}` in command responses
+pub struct TemplateVars<'a> {
+    pub user: &'a str,
+    pub channel: &'a str,
+    pub args: &'a [&'a str],
+    /// Times the command has been used
+    pub count: u64,
+    pub uptime: Uptime,
+    /// Counters of the channel, for `{counter:name}`
+    pub counters: Option<&'a BTreeMap<String, i64>>,
+    /// Other variables, like `{months}` for a resub
+    pub extra: &'a [(&'a str, String)],
+}
+
+enum Part<'a> {
+    Text(&'a str),
+    Var(&'a str, Option<&'a str>),
+}
+
+/// Splits a template into text and `{name}` / `{name:arg}` variables
+/// A `{` without a matching `}` is just text
+fn parts(template: &str) -> Vec<Part<'_>> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        if start > 0 {
+            parts.push(Part::Text(&rest[..start]));
+        }
+
+        let var = &rest[start + 1..start + len];
+        let mut name_arg = var.splitn(2, ':');
+        let name = name_arg.next().unwrap_or_default().trim();
+        parts.push(Part::Var(name, name_arg.next().map(str::trim)));
+
+        rest = &rest[start + len + 1..];
+    }
+
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest));
+    }
+
+    parts
+}
+
+/// Parses the `low-high` argument of `{random}`
+fn random_range(arg: Option<&str>) -> Option<(i64, i64)> {
+    let (low, high) = arg?.split_once('-')?;
+    let (low, high) = (low.trim().parse().ok()?, high.trim().parse().ok()?);
+
+    if low <= high {
+        Some((low, high))
+    } else {
+        None
+    }
+}
+
+/// Index of an `{argN}` variable, starting at 1
+fn arg_index(name: &str) -> Option<usize> {
+    name.strip_prefix("arg")?.parse().ok().filter(|idx| *idx > 0)
+}
+
+/// Checks that a template only uses known variables
+/// # Errors
+/// Returns a description of the first unknown or malformed variable
+pub fn validate(template: &str) -> Result<(), String> {
+    validate_with(template, &[])
+}
+
+/// Checks that a template only uses known variables, or `extra` ones
+/// # Errors
+/// Returns a description of the first unknown or malformed variable
+pub fn validate_with(template: &str, extra: &[&str]) -> Result<(), String> {
+    for part in parts(template) {
+        if let Part::Var(name, arg) = part {
+            let known = match name {
+                "user" | "channel" | "args" | "touser" | "count" | "uptime" => arg.is_none(),
+                _ if extra.contains(&name) => arg.is_none(),
+                "random" => random_range(arg).is_some(),
+                "counter" => arg.is_some_and(|name| !name.is_empty()),
+                _ => arg.is_none() && arg_index(name).is_some(),
+            };
+            if !known {
+                return Err(arg.map_or_else(
+                    || format!("unknown variable {{{name}}}"),
+                    |arg| format!("unknown variable {{{name}:{arg}}}"),
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Fills in the variables of a template, unknown variables are left as they are
+#[must_use]
+pub fn render(template: &str, vars: &TemplateVars<'_>) -> String {
+    let mut rendered = String::with_capacity(template.len());
+
+    for part in parts(template) {
+        match part {
+            Part::Text(text) => rendered.push_str(text),
+            Part::Var(name, arg) => match name {
+                "user" => rendered.push_str(vars.user),
+                "channel" => rendered.push_str(vars.channel),
+                "args" => rendered.push_str(&vars.args.join(" ")),
+                "touser" => rendered.push_str(
+                    vars.args
+                        .first()
+                        .map_or(vars.user, |user| user.trim_start_matches('@')),
+                ),
+                "count" => rendered.push_str(&vars.count.to_string()),
+                "uptime" => match vars.uptime {
+                    Uptime::Offline => rendered.push_str("offline"),
+                    Uptime::Unknown => rendered.push_str("unknown"),
+                    Uptime::Live(uptime) => rendered.push_str(&format_duration(uptime)),
+                },
+                "random" => match random_range(arg) {
+                    Some((low, high)) => {
+                        rendered.push_str(&thread_rng().sample(Uniform::new_inclusive(low, high)).to_string());
+                    }
+                    None => push_var(&mut rendered, name, arg),
+                },
+                "counter" => match arg {
+                    Some(counter) => {
+                        let value = vars
+                            .counters
+                            .and_then(|counters| counters.get(&counter.to_lowercase()))
+                            .copied()
+                            .unwrap_or_default();
+                        rendered.push_str(&value.to_string());
+                    }
+                    None => push_var(&mut rendered, name, arg),
+                },
+                _ => match (arg_index(name), vars.extra.iter().find(|(extra, _)| *extra == name)) {
+                    (Some(idx), _) => rendered.push_str(vars.args.get(idx - 1).copied().unwrap_or_default()),
+                    (None, Some((_, value))) if arg.is_none() => rendered.push_str(value),
+                    _ => push_var(&mut rendered, name, arg),
+                },
+            },
+        }
+    }
+
+    rendered
+}
+
+fn push_var(rendered: &mut String, name: &str, arg: Option<&str>) {
+    rendered.push('{');
+    rendered.push_str(name);
+    if let Some(arg) = arg {
+        rendered.push(':');
+        rendered.push_str(arg);
+    }
+    rendered.push('}');
+}
+
+/// Formats a duration like `2h 5m`
+#[must_use]
+pub fn format_duration(duration: Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    let (hours, minutes) = (minutes / 60, minutes % 60);
+
+    if hours > 0 {
+        format!("{hours}h {minutes}m")
+    } else {
+        format!("{minutes}m")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_variables() {
+        let vars = TemplateVars {
+            user: "NeonRaytracer",
+            channel: "neonraytracer",
+            args: &["@bob", "two"],
+            count: 3,
+            uptime: Uptime::Live(Duration::from_secs(2 * 60 * 60 + 5 * 60)),
+            counters: Some(&BTreeMap::from([("deaths".to_string(), 7)])),
+            extra: &[("months", "6".to_string())],
+        };
+
+        assert_eq!(
+            render("Hi {touser}, {user} used this {count} times ({arg2}) {uptime} {", &vars),
+            "Hi bob, NeonRaytracer used this 3 times (two) 2h 5m {"
+        );
+        assert_eq!(render("{counter:Deaths} {counter:bugs}", &vars), "7 0");
+        assert_eq!(render("{months} {years}", &vars), "6 {years}");
+        let roll: i64 = render("{random:1-6}", &vars).parse().unwrap();
+        assert!((1..=6).contains(&roll));
+        // The whole range can't overflow
+        let roll: i64 = render("{random:0-9223372036854775807}", &vars).parse().unwrap();
+        assert!(roll >= 0);
+        assert_eq!(render("{random:9223372036854775807-9223372036854775807}", &vars), i64::MAX.to_string());
+    }
+
+    #[test]
+    fn validates_variables() {
+        assert!(validate("{user} {arg1} {random:1-100} {uptime}").is_ok());
+        assert!(validate("{usr}").is_err());
+        assert!(validate("{random:10-1}").is_err());
+        assert!(validate("{random:0-9223372036854775807}").is_ok());
+        assert!(validate("{arg0}").is_err());
+        assert!(validate("{counter:deaths}").is_ok());
+        assert!(validate("{counter}").is_err());
+        assert!(validate("{months}").is_err());
+        assert!(validate_with("{months}", &["months"]).is_ok());
+    }
+}
@@ -0,0 +1,155 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::twitch::stream_status::StreamStatus;
+
+/// A message posted to chat on an interval, from the `timers` setting
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Timer {
+    pub name: String,
+    pub message: String,
+    /// Seconds between messages
+    pub interval: u64,
+    /// Chat lines from others needed since the last time, so the bot doesn't talk to itself
+    #[serde(default)]
+    pub min_lines: u64,
+    /// Only post while the channel is live
+    #[serde(default)]
+    pub online_only: bool,
+    /// Channels to post in, all joined channels if empty
+    #[serde(default)]
+    pub channels: Vec<String>,
+}
+
+impl Timer {
+    fn posts_in(&self, channel: &str) -> bool {
+        self.channels.is_empty()
+            || self
+                .channels
+                .iter()
+                .any(|timer_channel| timer_channel.trim_start_matches('#').eq_ignore_ascii_case(channel))
+    }
+}
+
+struct TimerState {
+    last_sent: Instant,
+    lines: u64,
+    count: u64,
+}
+
+impl TimerState {
+    fn new() -> Self {
+        Self {
+            last_sent: Instant::now(),
+            lines: 0,
+            count: 0,
+        }
+    }
+}
+
+/// A timer that's due, with the times it has been posted in the channel
+pub struct DueTimer {
+    pub channel: String,
+    pub message: String,
+    pub count: u64,
+}
+
+/// Keeps track of when timers were posted and how much chat happened since, per channel
+#[derive(Default)]
+pub struct Timers {
+    timers: Vec<Timer>,
+    state: HashMap<(String, String), TimerState>,
+}
+
+impl Timers {
+    /// Replaces the timers, timers that still exist keep their progress
+    pub fn set(&mut self, timers: Vec<Timer>) {
+        self.state
+            .retain(|(_, name), _| timers.iter().any(|timer| timer.name == *name));
+        self.timers = timers;
+    }
+
+    /// Counts a chat line towards `min_lines` of the channel's timers
+    pub fn chat_line(&mut self, channel: &str) {
+        for timer in self.timers.iter().filter(|timer| timer.posts_in(channel)) {
+            self.state
+                .entry((channel.to_string(), timer.name.clone()))
+                .or_insert_with(TimerState::new)
+                .lines += 1;
+        }
+    }
+
+    /// Timers that should be posted now, they're counted as sent
+    pub fn due(&mut self, channels: &[String], stream_status: &StreamStatus) -> Vec<DueTimer> {
+        let mut due = Vec::new();
+
+        for channel in channels {
+            for timer in self.timers.iter().filter(|timer| timer.posts_in(channel)) {
+                let state = self
+                    .state
+                    .entry((channel.clone(), timer.name.clone()))
+                    .or_insert_with(TimerState::new);
+
+                // The interval starts over when the channel goes live
+                if timer.online_only && !stream_status.is_live(channel) {
+                    state.last_sent = Instant::now();
+                    continue;
+                }
+                if state.last_sent.elapsed() < Duration::from_secs(timer.interval)
+                    || state.lines < timer.min_lines
+                {
+                    continue;
+                }
+
+                state.last_sent = Instant::now();
+                state.lines = 0;
+                state.count += 1;
+                due.push(DueTimer {
+                    channel: channel.clone(),
+                    message: timer.message.clone(),
+                    count: state.count,
+                });
+            }
+        }
+
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timer(min_lines: u64, online_only: bool) -> Timer {
+        Timer {
+            name: "discord".to_string(),
+            message: "Join the discord!".to_string(),
+            interval: 0,
+            min_lines,
+            online_only,
+            channels: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn waits_for_chat_lines_and_stream() {
+        let channels = vec!["neonraytracer".to_string()];
+        let mut status = StreamStatus::default();
+        let mut timers = Timers::default();
+        timers.set(vec![timer(2, true)]);
+
+        timers.chat_line("neonraytracer");
+        timers.chat_line("neonraytracer");
+        assert!(timers.due(&channels, &status).is_empty());
+
+        status.went_live("neonraytracer", std::time::SystemTime::now());
+        let due = timers.due(&channels, &status);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].message, "Join the discord!");
+        assert_eq!(due[0].count, 1);
+
+        timers.chat_line("neonraytracer");
+        assert!(timers.due(&channels, &status).is_empty());
+    }
+}
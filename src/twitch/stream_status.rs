@@ -0,0 +1,110 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long a channel has been live, for `{uptime}`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Uptime {
+    Offline,
+    /// The bot started mid stream and never saw it go live
+    Unknown,
+    Live(Duration),
+}
+
+/// Which channels are live, updated from `PubSub` and read by the chat bot
+#[derive(Debug, Default)]
+pub struct StreamStatus {
+    /// When the live channels started streaming, if the bot saw it
+    live_since: HashMap<String, Option<Instant>>,
+    games: HashMap<String, String>,
+    /// Bits cheered during the current (or last) stream
+    bits: HashMap<String, u64>,
+}
+
+pub type SharedStreamStatus = Rc<RefCell<StreamStatus>>;
+
+impl StreamStatus {
+    /// Marks a channel as live since `started`, keeps the start time if it's known already
+    /// A new stream starts its bits total over
+    pub fn went_live(&mut self, channel: &str, started: SystemTime) {
+        let channel = channel.to_lowercase();
+        let ago = SystemTime::now().duration_since(started).unwrap_or_default();
+        let since = Instant::now().checked_sub(ago);
+        if let Some(live_since) = self.live_since.get_mut(&channel) {
+            *live_since = live_since.or(since);
+        } else {
+            self.live_since.insert(channel.clone(), since);
+            self.bits.remove(&channel);
+        }
+    }
+
+    /// Marks a channel as live without knowing since when, like from a viewer count
+    pub fn seen_live(&mut self, channel: &str) {
+        self.live_since.entry(channel.to_lowercase()).or_default();
+    }
+
+    pub fn went_offline(&mut self, channel: &str) {
+        self.live_since.remove(&channel.to_lowercase());
+    }
+
+    #[must_use]
+    pub fn is_live(&self, channel: &str) -> bool {
+        self.live_since.contains_key(channel)
+    }
+
+    #[must_use]
+    pub fn uptime(&self, channel: &str) -> Uptime {
+        match self.live_since.get(channel) {
+            None => Uptime::Offline,
+            Some(None) => Uptime::Unknown,
+            Some(Some(since)) => Uptime::Live(since.elapsed()),
+        }
+    }
+
+    /// What's being played, if a moderator told the bot with `!setgame`
+    #[must_use]
+    pub fn game(&self, channel: &str) -> Option<&str> {
+        self.games.get(channel).map(String::as_str)
+    }
+
+    /// Sets the game of a channel, an empty game clears it
+    pub fn set_game(&mut self, channel: &str, game: &str) {
+        if game.is_empty() {
+            self.games.remove(&channel.to_lowercase());
+        } else {
+            self.games.insert(channel.to_lowercase(), game.to_string());
+        }
+    }
+
+    /// Adds a cheer to the channel's total for this stream and returns the new total
+    pub fn add_bits(&mut self, channel: &str, bits: u64) -> u64 {
+        let total = self.bits.entry(channel.to_lowercase()).or_default();
+        *total += bits;
+        *total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uptime_is_only_known_from_stream_up() {
+        let mut status = StreamStatus::default();
+        assert_eq!(status.uptime("neon"), Uptime::Offline);
+
+        status.seen_live("neon");
+        assert!(status.is_live("neon"));
+        assert_eq!(status.uptime("neon"), Uptime::Unknown);
+
+        status.went_live("neon", SystemTime::now() - Duration::from_secs(60 * 60));
+        let Uptime::Live(uptime) = status.uptime("neon") else {
+            panic!("the stream-up should set the uptime");
+        };
+        assert!(uptime >= Duration::from_secs(60 * 60));
+
+        status.went_offline("neon");
+        assert_eq!(status.uptime("neon"), Uptime::Offline);
+    }
+}
@@ -11,8 +11,10 @@ use rand::{thread_rng, Rng};
 
 pub mod channel_point_redemption;
 pub mod follower;
+pub mod video_playback;
 use channel_point_redemption::points_redeemed;
 use follower::new_follower;
+use video_playback::stream_status_changed;
 
 use crate::{
     common_structs::{
@@ -20,6 +22,7 @@ use crate::{
         twitch::pubsub_topics_msg::*,
     },
     nonce,
+    twitch::{chat_bot::ChatSettings, stream_status::SharedStreamStatus},
 };
 
 pub struct TwidshPubSubBott {
@@ -32,6 +35,21 @@ pub struct TwidshPubSubBott {
    pub last_ping: Instant,
    pub pong_timeout: Duration,
    pub settings: Rc<config::Config>,
+   pub stream_status: SharedStreamStatus,
+}
+
+/// Topics for the live status of every chat channel
+fn live_status_topics(settings: &config::Config) -> Vec<String> {
+    ChatSettings::from_config(settings).map_or_else(
+        |_| Vec::new(),
+        |chat_settings| {
+            chat_settings
+                .channels
+                .iter()
+                .map(|channel| format!("video-playback.{channel}"))
+                .collect()
+        },
+    )
 }
 
 impl TwidshPubSubBott {
@@ -93,6 +111,7 @@ impl TwidshPubSubBott {
                         }
 
                         "following" => new_follower(&msg),
+                        "video-playback" => stream_status_changed(&msg, &self.stream_status),
                         _ => {}
                     }
                 }
@@ -111,19 +130,56 @@ impl TwidshPubSubBott {
         channel_id: String,
         twitch_auth_token: String,
     ) -> TopicListenerMeta {
+        let mut topics = vec![
+            format!("channel-points-channel-v1.{}", &channel_id),
+            format!("following.{}", &channel_id),
+        ];
+        topics.extend(live_status_topics(&self.settings));
+
         TopicListenerMeta {
             event: "LISTEN".to_string(),
             nonce,
             data: TopicListenerData {
-                topics: vec![
-                    format!("channel-points-channel-v1.{}", &channel_id),
-                    format!("following.{}", &channel_id),
-                ],
+                topics,
                 auth_token: twitch_auth_token,
             },
         }
     }
 
+    /// Uses reloaded settings, listening to the live status of added chat channels
+    /// and no longer to removed ones
+    pub fn apply_settings(&mut self, settings: config::Config) {
+        let old_topics = live_status_topics(&self.settings);
+        let new_topics = live_status_topics(&settings);
+        self.settings = Rc::new(settings);
+
+        let added = new_topics.iter().filter(|topic| !old_topics.contains(topic)).cloned().collect();
+        let removed = old_topics.into_iter().filter(|topic| !new_topics.contains(topic)).collect();
+        self.send_topics("LISTEN", added);
+        self.send_topics("UNLISTEN", removed);
+    }
+
+    /// Starts or stops listening to single topics, a failed write is fixed by the listen on reconnect
+    fn send_topics(&mut self, event: &str, topics: Vec<String>) {
+        if topics.is_empty() {
+            return;
+        }
+        info!("PubSub {}: {}", event, topics.join(", "));
+
+        let msg = TopicListenerMeta {
+            event: event.to_string(),
+            nonce: nonce(),
+            data: TopicListenerData {
+                topics,
+                auth_token: var("T_AUTH_TOKEN").expect("Twitch auth token not found"),
+            },
+        };
+        let json = serde_json::to_string(&msg).expect("Failed to serialize listen msg");
+        if let Err(err) = self.socket.write_message(Message::Text(json)) {
+            error!("Could not write PubSub {} msg:\n{}", event, err);
+        }
+    }
+
     pub fn send_listen_msg(&mut self) {
         let twitch_auth_token = var("T_AUTH_TOKEN").expect("Twitch auth token not found");
 
@@ -0,0 +1,52 @@
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{
+    common_structs::twitch::pubsub_topics_msg::TopicsResMetaMsg,
+    twitch::stream_status::SharedStreamStatus,
+};
+
+#[derive(Deserialize, Serialize)]
+struct VideoPlayback {
+    #[serde(rename = "type")]
+    event: String,
+    /// Unix seconds, when a `stream-up` happened
+    server_time: Option<f64>,
+}
+
+/// Tracks when channels go live or offline, from `video-playback.<login>` topics
+pub fn stream_status_changed(res_msg: &TopicsResMetaMsg, stream_status: &SharedStreamStatus) {
+    let playback: VideoPlayback = match serde_json::from_str(res_msg.data.message.as_str()) {
+        Ok(playback) => playback,
+        Err(err) => {
+            error!("Could not deserialize Twitch video playback: {}", err);
+            return;
+        }
+    };
+    let channel = res_msg
+        .data
+        .topic
+        .split_once('.')
+        .map_or("", |(_, channel)| channel);
+
+    match playback.event.as_str() {
+        "stream-up" => {
+            info!("#{} went live!", channel);
+            let started = playback
+                .server_time
+                .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                .and_then(|since_epoch| UNIX_EPOCH.checked_add(since_epoch))
+                .unwrap_or_else(SystemTime::now);
+            stream_status.borrow_mut().went_live(channel, started);
+        }
+        "stream-down" => {
+            info!("#{} went offline", channel);
+            stream_status.borrow_mut().went_offline(channel);
+        }
+        // Viewer counts are only sent while live, the bot might've started mid stream
+        // but when the stream started isn't known then
+        "viewcount" => stream_status.borrow_mut().seen_live(channel),
+        _ => {}
+    }
+}